@@ -0,0 +1,197 @@
+/// An RGB color
+pub type Color = [u8; 3];
+
+/// Colors set through SGR escape sequences, `None` leaves the theme's color
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+	pub fg: Option<Color>,
+	pub bg: Option<Color>,
+}
+
+/// The 16 basic colors as xterm shows them
+const BASIC_COLORS: [Color; 16] = [
+	[0x00, 0x00, 0x00],
+	[0xcd, 0x00, 0x00],
+	[0x00, 0xcd, 0x00],
+	[0xcd, 0xcd, 0x00],
+	[0x00, 0x00, 0xee],
+	[0xcd, 0x00, 0xcd],
+	[0x00, 0xcd, 0xcd],
+	[0xe5, 0xe5, 0xe5],
+	[0x7f, 0x7f, 0x7f],
+	[0xff, 0x00, 0x00],
+	[0x00, 0xff, 0x00],
+	[0xff, 0xff, 0x00],
+	[0x5c, 0x5c, 0xff],
+	[0xff, 0x00, 0xff],
+	[0x00, 0xff, 0xff],
+	[0xff, 0xff, 0xff],
+];
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// Strips the escape sequences from `bytes`, returning the remaining bytes
+/// and where the style changes as byte offsets into them
+pub fn parse(bytes: &[u8]) -> (Vec<u8>, Vec<(usize, Style)>) {
+	let mut text = Vec::with_capacity(bytes.len());
+	let mut changes: Vec<(usize, Style)> = Vec::new();
+	let mut style = Style::default();
+	let mut i = 0;
+	while i < bytes.len() {
+		if bytes[i] != ESC {
+			text.push(bytes[i]);
+			i += 1;
+			continue;
+		}
+		match bytes.get(i + 1) {
+			// Control sequence: parameters, intermediates and a final byte
+			Some(b'[') => {
+				let start = i + 2;
+				let end = bytes[start..]
+					.iter()
+					.position(|byte| (0x40..=0x7e).contains(byte))
+					.map_or(bytes.len(), |end| start + end);
+				if bytes.get(end) == Some(&b'm') {
+					apply_sgr(&mut style, &bytes[start..end]);
+					match changes.last_mut() {
+						Some((offset, last)) if *offset == text.len() => *last = style,
+						_ => changes.push((text.len(), style)),
+					}
+				}
+				i = end + 1;
+			}
+			// Operating system command, terminated by BEL or ST
+			Some(b']') => {
+				let mut end = i + 2;
+				while end < bytes.len() {
+					if bytes[end] == BEL {
+						end += 1;
+						break;
+					}
+					if bytes[end] == ESC && bytes.get(end + 1) == Some(&b'\\') {
+						end += 2;
+						break;
+					}
+					end += 1;
+				}
+				i = end;
+			}
+			// Anything else is taken to be a two byte sequence
+			_ => i += 2,
+		}
+	}
+	// Drop changes that don't affect any text
+	changes.dedup_by(|next, previous| next.1 == previous.1);
+	(text, changes)
+}
+
+/// Applies the parameters of a `CSI ... m` sequence
+fn apply_sgr(style: &mut Style, parameters: &[u8]) {
+	let parameters = String::from_utf8_lossy(parameters);
+	// Colon separated sub-parameters are treated like regular ones
+	let mut parameters = parameters
+		.split([';', ':'])
+		.map(|parameter| parameter.parse::<u16>().unwrap_or(0));
+	while let Some(parameter) = parameters.next() {
+		match parameter {
+			0 => *style = Style::default(),
+			30..=37 => style.fg = Some(BASIC_COLORS[parameter as usize - 30]),
+			38 => style.fg = extended_color(&mut parameters),
+			39 => style.fg = None,
+			40..=47 => style.bg = Some(BASIC_COLORS[parameter as usize - 40]),
+			48 => style.bg = extended_color(&mut parameters),
+			49 => style.bg = None,
+			90..=97 => style.fg = Some(BASIC_COLORS[parameter as usize - 90 + 8]),
+			100..=107 => style.bg = Some(BASIC_COLORS[parameter as usize - 100 + 8]),
+			// Attributes like bold or underline can't be rendered with our font
+			_ => (),
+		}
+	}
+}
+
+/// Reads the color following a `38` or `48`, either `5;INDEX` or `2;R;G;B`
+fn extended_color(parameters: &mut impl Iterator<Item = u16>) -> Option<Color> {
+	let mut component = || parameters.next().map(|i| i.min(0xff) as u8);
+	match component()? {
+		5 => Some(indexed_color(component()?)),
+		2 => Some([component()?, component()?, component()?]),
+		_ => None,
+	}
+}
+
+/// Colors of the 256 color palette
+fn indexed_color(index: u8) -> Color {
+	match index {
+		0..=15 => BASIC_COLORS[index as usize],
+		// 6x6x6 color cube
+		16..=231 => {
+			let level = |i: u8| if i == 0 { 0 } else { 55 + i * 40 };
+			let index = index - 16;
+			[level(index / 36), level(index / 6 % 6), level(index % 6)]
+		}
+		// Grayscale ramp
+		232..=255 => {
+			let level = 8 + (index - 232) * 10;
+			[level; 3]
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RED: Style = Style {
+		fg: Some(BASIC_COLORS[1]),
+		bg: None,
+	};
+
+	#[test]
+	fn strips_sgr_sequences() {
+		let (text, changes) = parse(b"a\x1b[31mbc\x1b[0md");
+		assert_eq!(text, b"abcd");
+		assert_eq!(changes, [(1, RED), (3, Style::default())]);
+	}
+
+	#[test]
+	fn offsets_count_bytes() {
+		let (text, changes) = parse("é\x1b[31mx".as_bytes());
+		assert_eq!(text, "éx".as_bytes());
+		assert_eq!(changes, [(2, RED)]);
+	}
+
+	#[test]
+	fn merges_sequences_at_the_same_offset() {
+		let (text, changes) = parse(b"\x1b[31m\x1b[42mx");
+		assert_eq!(text, b"x");
+		let style = Style {
+			fg: Some(BASIC_COLORS[1]),
+			bg: Some(BASIC_COLORS[2]),
+		};
+		assert_eq!(changes, [(0, style)]);
+	}
+
+	#[test]
+	fn drops_changes_that_keep_the_style() {
+		let (_, changes) = parse(b"a\x1b[31mb\x1b[31mc");
+		assert_eq!(changes, [(1, RED)]);
+	}
+
+	#[test]
+	fn skips_other_sequences() {
+		let (text, changes) = parse(b"a\x1b[2Kb\x1b]0;title\x07c\x1b]8;;url\x1b\\d\x1b7e");
+		assert_eq!(text, b"abcde");
+		assert!(changes.is_empty());
+	}
+
+	#[test]
+	fn extended_colors() {
+		let (_, changes) = parse(b"\x1b[38;5;196;48;2;1;2;3mx");
+		let style = Style {
+			fg: Some([0xff, 0, 0]),
+			bg: Some([1, 2, 3]),
+		};
+		assert_eq!(changes, [(0, style)]);
+	}
+}
@@ -1,155 +1,723 @@
-use crate::events::{Event, Keycode, Modifiers};
-use crate::fonts::Font;
-use crate::picker::Picker;
-use crate::{Mode, THEME};
-use log::{debug, log_enabled, Level};
+use crate::ansi::{self, Style};
+use crate::bindings::{Action, Key};
+use crate::config::{Colors, Config, Destination, Info, Launcher};
+use crate::events::{Clipboard, Event, Request};
+use crate::execute::{self, Environment, Placeholders};
+use crate::fonts::{Font, Fonts};
+use crate::frecency::Frecency;
+use crate::history::History;
+use crate::input;
+use crate::picker::{Choice, Picker};
+use crate::{Mode, FONT_SIZE, THEME};
+use log::{debug, log_enabled, warn, Level};
+use std::io::Write;
+use std::ops::Range;
+use std::process::Child;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// Exit code when accepting with nothing matched, like fzf
+const NO_MATCH: i32 = 1;
+/// Exit code when the user backs out, like fzf
+pub const ABORTED: i32 = 130;
+/// Number of columns marking text cut off at either end of an item, as `..`
+const ELLIPSIS_WIDTH: usize = 2;
+/// Frames of the spinner shown while options are still being read
+const SPINNER: [char; 4] = ['-', '\\', '|', '/'];
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What was accepted to be launched
+pub enum Launch {
+	/// The option at the index, in the order of the input
+	Option(usize),
+	/// A shell command
+	Command(String),
+}
 
 /// Responsible for event handling and drawing to screen
 pub struct App {
 	// Some internal state
 	picker: Picker,
 	font: Font,
+	/// Where `font` comes from when the scale changes
+	fonts: Fonts,
+	config: Config,
 	exit_code: i32,
 	running: bool,
+	requests: Vec<Request>,
+	/// Input method composition shown at the query cursor, with the cursor
+	/// position inside it
+	preedit: Option<(String, Option<usize>)>,
+	/// First column of the query line that is visible
+	query_scroll: usize,
+	/// Options still being read, `None` once the input is exhausted
+	input: Option<Receiver<Choice>>,
+	started: Instant,
+	history: Option<History>,
+	frecency: Option<Frecency>,
+	/// The latest reload command, cancelled by the next one
+	command: Option<Child>,
+	/// Selection from before a reload, moved to once it shows up again
+	reselect: Option<String>,
+	/// Written to stdout on exit
+	output: Vec<u8>,
+	launch: Option<Launch>,
 }
 
 impl App {
-	pub fn new(font: Font, options: Vec<String>) -> Self {
+	pub fn new(fonts: Fonts, input: Receiver<Choice>, config: Config) -> Self {
+		let history = config
+			.history
+			.clone()
+			.map(|path| History::load(path, config.history_size));
+		let frecency = config
+			.frecency_db
+			.clone()
+			.map(|path| Frecency::new(path, config.frecency_namespace.clone()));
+		let mut picker = Picker::new(Vec::new(), config.sorting.clone(), config.case);
+		picker.search.set_query(&config.query);
+		// What follows the command are its arguments
+		picker.set_first_word_only(config.launcher == Some(Launcher::Run));
+		if let Some(frecency) = frecency.as_ref().filter(|_| config.frecency_weight > 0.0) {
+			match frecency.scores() {
+				Ok(scores) => picker.set_boosts(
+					scores
+						.into_iter()
+						.map(|(item, score)| {
+							(item, (score * config.frecency_weight).round() as i64)
+						})
+						.collect(),
+				),
+				Err(err) => warn!("Failed to read the frecency store: {err:#}"),
+			}
+		}
 		App {
-			font,
-			picker: Picker::new(options),
+			font: fonts.base(),
+			fonts,
+			history,
+			frecency,
+			picker,
+			config,
 			exit_code: 0,
 			running: true,
+			requests: Vec::new(),
+			preedit: None,
+			query_scroll: 0,
+			input: Some(input),
+			started: Instant::now(),
+			command: None,
+			reselect: None,
+			output: Vec::new(),
+			launch: None,
 		}
 	}
 	pub fn handle_events(&mut self, event: Event) {
 		debug!("{:?}", event);
+		if !self.running {
+			return;
+		}
+		let query = self.picker.query().to_string();
+		self.handle_event(event);
+		if self.running && self.picker.query() != query {
+			if let Some(action) = self.config.bindings.on_change() {
+				self.perform(action.clone());
+			}
+		}
+	}
+	fn handle_event(&mut self, event: Event) {
 		match event {
-			Event::Focused(false) => {
-				if !log_enabled!(Level::Debug) {
-					self.close(1);
-				}
+			// Clicking elsewhere counts as aborting
+			Event::Focused(false) if !log_enabled!(Level::Debug) => self.close(ABORTED),
+			Event::MiddleClick => self.requests.push(Request::Paste(Clipboard::Primary)),
+			Event::Paste(text) | Event::Commit(text) => self.picker.search.insert_str(&text),
+			Event::Preedit { text, cursor } => {
+				self.preedit = (!text.is_empty()).then_some((text, cursor));
 			}
-			Event::Keyboard {
-				keycode: Keycode::Escape,
-				..
-			}
-			| Event::Keyboard {
-				keycode: Keycode::c,
-				modifiers: Modifiers { ctrl: true, .. },
-				..
-			} => self.close(1),
+			Event::DeleteSurrounding { before, after } => {
+				self.picker.search.delete_surrounding(before, after)
+			}
+			Event::Action(action) => self.perform(action),
 			Event::Keyboard {
 				modifiers,
 				keycode,
 				utf8,
 			} => {
-				let picker = &mut self.picker;
+				let key = Key::from_event(&modifiers, keycode);
+				// Expected keys take precedence over bindings, like in fzf
+				if self.config.expect.contains(&key) {
+					self.accept(self.config.destination, Some(key));
+					return;
+				}
+				if let Some(action) = self.config.bindings.get(&key) {
+					self.perform(action.clone());
+					return;
+				}
 				let special_modifiers = modifiers.ctrl | modifiers.alt | modifiers.logo;
 				if let Some(Some(ch)) = utf8.map(|i| i.chars().last()) {
-					if !special_modifiers && (' '..='~').contains(&ch) {
-						picker.search.insert(ch);
+					if !special_modifiers && !ch.is_control() {
+						self.picker.search.insert(ch);
 					}
 				}
-				// Ctrl + <T> keycodes
-				if modifiers.ctrl {
-					match keycode {
-						Keycode::n => picker.next(),
-						Keycode::p => picker.prev(),
-						Keycode::a => picker.search.cursor_to_start(),
-						Keycode::e => picker.search.cursor_to_end(),
-						Keycode::w => picker.search.delete_word(),
-						Keycode::u => picker.search.delete_till_start(),
-						Keycode::k => picker.search.delete_till_end(),
-						Keycode::b => picker.search.cursor_left(),
-						Keycode::f => picker.search.cursor_right(),
-						_ => (),
+			}
+			_ => (),
+		}
+	}
+	fn perform(&mut self, action: Action) {
+		let picker = &mut self.picker;
+		match action {
+			Action::Accept => self.accept(self.config.destination, None),
+			Action::AcceptClipboard => self.accept(Destination::Clipboard, None),
+			Action::Abort => self.close(ABORTED),
+			Action::Up => {
+				picker.prev();
+				self.reselect = None;
+			}
+			Action::Down => {
+				picker.next();
+				self.reselect = None;
+			}
+			Action::BackwardChar => picker.search.cursor_left(),
+			Action::ForwardChar => picker.search.cursor_right(),
+			Action::BeginningOfLine => picker.search.cursor_to_start(),
+			Action::EndOfLine => picker.search.cursor_to_end(),
+			Action::BackwardDeleteChar => picker.search.delete(),
+			Action::UnixWordRubout => picker.search.delete_word(),
+			Action::UnixLineDiscard => picker.search.delete_till_start(),
+			Action::KillLine => picker.search.delete_till_end(),
+			Action::Paste => self.requests.push(Request::Paste(Clipboard::Regular)),
+			Action::PastePrimary => self.requests.push(Request::Paste(Clipboard::Primary)),
+			Action::PreviousHistory => {
+				if let Some(query) = self
+					.history
+					.as_mut()
+					.and_then(|history| history.previous(picker.query()))
+				{
+					picker.search.set_query(query);
+				}
+			}
+			Action::Execute(command) => {
+				if let Some(command) = execute::expand(&command, &self.placeholders()) {
+					if let Err(err) = execute::spawn(&command, &self.config.env) {
+						warn!("Failed to run {command:?}: {err}");
 					}
 				}
-				match keycode {
-					Keycode::Return => {
-						println!("{}", self.picker.selection().unwrap_or(self.picker.query()));
-						self.close(0);
+			}
+			// Every later client of the daemon would be left without one
+			Action::Become(_) if self.config.daemon => {
+				warn!("become isn't supported by the daemon");
+			}
+			Action::Become(command) => {
+				if let Some(command) = execute::expand(&command, &self.placeholders()) {
+					let err = execute::become_command(&command);
+					warn!("Failed to run {command:?}: {err}");
+				}
+			}
+			Action::Reload(command) => self.reload(&command),
+			Action::ChangeQuery(query) => picker.search.set_query(&query),
+			Action::NextHistory => {
+				if let Some(query) = self.history.as_mut().and_then(|history| history.next()) {
+					picker.search.set_query(query);
+				}
+			}
+		}
+	}
+	/// Replaces the options with the output of `command`
+	fn reload(&mut self, command: &str) {
+		let Some(command) = execute::expand(command, &self.placeholders()) else {
+			return;
+		};
+		self.stop_command();
+		match input::start_command(
+			&command,
+			self.config.input_delimiter(),
+			self.config.choice_parser(),
+			&self.config.env,
+		) {
+			Ok((child, input)) => {
+				self.reselect = self
+					.picker
+					.selection()
+					.map(str::to_string)
+					.or(self.reselect.take());
+				self.picker.clear();
+				self.input = Some(input);
+				self.command = Some(child);
+			}
+			Err(err) => warn!("{err:#}"),
+		}
+	}
+	/// The state of the picker, for remote control
+	pub fn state(&mut self) -> serde_json::Value {
+		self.read_input();
+		self.picker.update();
+		let current = self.picker.selected_index().zip(self.picker.selection());
+		serde_json::json!({
+			"query": self.picker.query(),
+			"position": self.picker.cursor(),
+			"reading": self.input.is_some(),
+			"totalCount": self.picker.option_count(),
+			"matchCount": self.picker.match_count(),
+			"current": current.map(|(index, text)| serde_json::json!({
+				"index": index,
+				"text": text,
+			})),
+		})
+	}
+	fn placeholders(&self) -> Placeholders<'_> {
+		Placeholders {
+			selection: self.picker.selection(),
+			query: self.picker.query(),
+			index: self.picker.selected_index(),
+		}
+	}
+	/// Outputs the selection, `key` is the expected key it was accepted with
+	fn accept(&mut self, destination: Destination, key: Option<Key>) {
+		if let Some(history) = self
+			.history
+			.as_mut()
+			.filter(|_| !self.picker.query().is_empty())
+		{
+			if let Err(err) = history.save(self.picker.query()) {
+				warn!("Failed to save the history: {err}");
+			}
+		}
+		if let Some((frecency, selection)) = self.frecency.as_ref().zip(self.picker.selection()) {
+			if let Err(err) = frecency.record(selection) {
+				warn!("Failed to update the frecency store: {err:#}");
+			}
+		}
+		let mut output: Vec<Vec<u8>> = Vec::new();
+		if self.config.print_query {
+			output.push(self.picker.query().into());
+		}
+		// With `--expect`, the first line tells which key accepted, if any
+		if !self.config.expect.is_empty() {
+			output.push(key.map(|key| key.to_string()).unwrap_or_default().into());
+		}
+		match destination {
+			Destination::Stdout => {
+				let stripped;
+				let selection = match self.picker.selected_choice() {
+					// Escape sequences are left out unless asked for
+					Some(choice) if self.config.ansi && !self.config.keep_ansi => {
+						(stripped, _) = ansi::parse(choice.bytes());
+						Some(stripped.as_slice())
 					}
-					Keycode::BackSpace => picker.search.delete(),
-					Keycode::Up => picker.prev(),
-					Keycode::Down => picker.next(),
-					Keycode::Left => picker.search.cursor_left(),
-					Keycode::Right => picker.search.cursor_right(),
-					_ => (),
+					Some(choice) => Some(choice.bytes()),
+					// The query stands in for the selection, unless it's printed
+					// already
+					None if !self.config.print_query => Some(self.picker.query().as_bytes()),
+					None => None,
+				};
+				output.extend(selection.map(<[u8]>::to_vec));
+			}
+			Destination::Clipboard => {
+				let selection = self.picker.selection().unwrap_or(self.picker.query());
+				self.requests.push(Request::Copy(selection.to_string()))
+			}
+			// Launched once the window is done with
+			Destination::Launch => {
+				self.launch = match self.config.launcher {
+					Some(Launcher::Run) => self.run_command().map(Launch::Command),
+					_ => self.picker.selected_index().map(Launch::Option),
+				};
+				if self.launch.is_some() {
+					self.requests.push(Request::ActivationToken);
 				}
 			}
-			_ => (),
+		}
+		self.write_output(&output);
+		// dmenu succeeds with the query as well
+		let query_accepted = self.config.dmenu && matches!(destination, Destination::Stdout);
+		if self.picker.selection().is_some() || self.launch.is_some() || query_accepted {
+			self.close(0);
+		} else {
+			self.close(NO_MATCH);
+		}
+	}
+	/// The selected command followed by the arguments typed after the first
+	/// word of the query, like dmenu_run. Without a selection, the query is
+	/// taken to be a command line of its own.
+	fn run_command(&self) -> Option<String> {
+		let query = self.picker.query().trim();
+		match self.picker.selection() {
+			Some(command) => {
+				let arguments = query.find(char::is_whitespace).map_or("", |i| &query[i..]);
+				Some(format!("{}{arguments}", execute::quote(command)))
+			}
+			None => (!query.is_empty()).then(|| query.to_string()),
+		}
+	}
+	/// Adds each of `lines` to the output, followed by the output delimiter
+	fn write_output(&mut self, lines: &[Vec<u8>]) {
+		let delimiter = if self.config.print0 { b'\0' } else { b'\n' };
+		for line in lines {
+			self.output.extend_from_slice(line);
+			self.output.push(delimiter);
 		}
 	}
+	/// Settles `--select-1` and `--exit-0`, returns whether that finished the
+	/// picker so the window isn't needed
+	pub fn finish_early(&mut self) -> bool {
+		// Taking the clipboard needs a serial from the window's keyboard focus
+		let select_1 = self.config.select_1 && self.config.destination == Destination::Stdout;
+		if !select_1 && !self.config.exit_0 {
+			return false;
+		}
+		// Both depend on every option being read
+		if let Some(input) = self.input.take() {
+			input.iter().for_each(|choice| self.picker.push(choice));
+		}
+		self.picker.update();
+		match self.picker.match_count() {
+			0 if self.config.exit_0 => {
+				// Like fzf, the query is still printed
+				if self.config.print_query {
+					self.write_output(&[self.picker.query().into()]);
+				}
+				self.close(NO_MATCH);
+			}
+			1 if select_1 => self.accept(Destination::Stdout, None),
+			_ => return false,
+		}
+		true
+	}
+	/// Rasterizes the glyphs at the physical size for the given output scale
+	pub fn set_scale(&mut self, scale: f64) {
+		let font_size = (FONT_SIZE as f64 * scale).round() as usize;
+		if font_size == self.font.height {
+			return;
+		}
+		match self.fonts.sized(font_size) {
+			Ok(font) => self.font = font,
+			Err(err) => warn!("Failed to scale the font to {font_size}px: {err}"),
+		}
+	}
+	/// Draws onto `canvas`, which is `width` by `height` physical pixels in ARGB
 	pub fn draw(&mut self, canvas: &mut [u8], width: u32, height: u32) {
-		let line_count = height as usize / self.font.height;
+		let (width, height) = (width as usize, height as usize);
+		// A partially visible row at the bottom is still drawn, clipped to the canvas
+		let line_count = height.div_ceil(self.font.height);
+		let columns = width / self.font.width;
 		// 24-bit colors in ARGB format
-		const BACKGROUND: u32 = match THEME {
-			Mode::Dark => 0xff000000,
-			Mode::Light => 0xffffffff,
-		};
+		let [r, g, b] = self.config.colors.bg;
+		let background = u32::from_be_bytes([0xff, r, g, b]);
 		canvas.chunks_exact_mut(4).for_each(|chunk| {
 			let array: &mut [u8; 4] = chunk.try_into().unwrap();
-			*array = BACKGROUND.to_le_bytes();
+			*array = background.to_le_bytes();
 		});
-		let mut draw_line = |index, text: &str, selection: bool| {
-			let top_line = index * width as usize * self.font.height * 4;
-			for (i, symbol) in text.char_indices() {
-				let glyph = match self.font.get_glyph(symbol) {
-					Some(x) => x,
-					None => continue,
+		if line_count == 0 {
+			return;
+		}
+		self.read_input();
+		self.picker.update();
+		if let Some(selection) = &self.reselect {
+			// Stop looking once the options are complete
+			if self.picker.select(selection) || self.input.is_none() {
+				self.reselect = None;
+			}
+		}
+		let info = self.info_line();
+		let colors = &self.config.colors;
+		// `styles` holds the colors of the first columns, if the text has any
+		let mut draw_line = |index: usize, text: &str, selection: bool, styles: &[Style]| {
+			let top = index * self.font.height;
+			let visible_rows = self.font.height.min(height - top);
+			for (i, symbol) in text.chars().take(columns).enumerate() {
+				let style = styles.get(i).copied().unwrap_or_default();
+				// The font has no glyph for the replacement character standing in
+				// for invalid UTF-8
+				let symbol = match symbol {
+					char::REPLACEMENT_CHARACTER => '?',
+					symbol => symbol,
 				};
-				let top_left = top_line + i * self.font.width * 4;
-				for j in 0..self.font.height {
+				// Characters beyond ASCII still take up their column, so the
+				// cursor and preedit stay where they belong
+				let glyph = self.font.glyph_or_box(symbol);
+				let top_left = 4 * (top * width + i * self.font.width);
+				for j in 0..visible_rows {
 					for i in 0..self.font.width {
-						let index = top_left + 4 * (i + j * width as usize);
-						let mut pixel_value = glyph[i + j * self.font.width];
-						if selection {
-							pixel_value = 0xff - pixel_value;
-						}
-						canvas[index] = pixel_value;
-						canvas[index + 1] = pixel_value;
-						canvas[index + 2] = pixel_value;
+						let index = top_left + 4 * (i + j * width);
+						let pixel_value = glyph[i + j * self.font.width];
+						canvas[index..index + 3].copy_from_slice(&blend(
+							colors,
+							style,
+							selection,
+							pixel_value,
+						));
 					}
 				}
 			}
 		};
-		draw_line(0, self.picker.query(), false);
-		// TODO: Handle text and cursor rendering when the text width is greater than canvas width
-		self.picker.update();
-		// -1 since one line is taken by search
+		// The inline info takes the end of the query line, as long as it fits
+		let query_columns = match self.config.info {
+			Info::Inline if info.len() < columns => columns - info.len(),
+			_ => columns,
+		};
+		// The prompt goes first, cut short if there's no room for it
+		let prompt: String = self.config.prompt.chars().take(query_columns).collect();
+		let prompt_width = prompt.chars().count();
+		let query_columns = query_columns - prompt_width;
+		let (query, cursor, preedit) = self.query_line();
+		// Scroll just enough to keep the cursor in view, +1 leaves room for it past the end
+		let length = query.chars().count();
+		self.query_scroll = self
+			.query_scroll
+			.min(cursor)
+			.min((length + 1).saturating_sub(query_columns))
+			.max((cursor + 1).saturating_sub(query_columns))
+			// Without any room for the query, as with a long prompt, there's
+			// nothing to keep in view
+			.min(cursor);
+		let query: String = query
+			.chars()
+			.skip(self.query_scroll)
+			.take(query_columns)
+			.collect();
+		match self.config.info {
+			Info::Inline => draw_line(
+				0,
+				&format!("{prompt}{query:<query_columns$}{info}"),
+				false,
+				&[],
+			),
+			_ => draw_line(0, &format!("{prompt}{query}"), false, &[]),
+		}
+		let cursor = cursor.saturating_sub(self.query_scroll);
+		let preedit = preedit.start.saturating_sub(self.query_scroll)
+			..preedit.end.saturating_sub(self.query_scroll);
+		// Items go below the search line, and the info line if it has one
+		let first_item = match self.config.info {
+			Info::Default => {
+				if line_count > 1 {
+					draw_line(1, &info, false, &[]);
+				}
+				2
+			}
+			Info::Inline | Info::Hidden => 1,
+		};
+		// -2 for the selection marker in front of each item
+		let item_columns = columns.saturating_sub(2);
 		self.picker
-			.get_matches(line_count - 1)
+			.get_matches(line_count.saturating_sub(first_item))
 			.enumerate()
-			.for_each(|(i, mtch)| {
+			.for_each(|(i, (choice, first_match))| {
 				let selection = i == self.picker.selection_index();
+				let text = choice.text();
+				let start = scroll_to(first_match, text.chars().count(), item_columns);
+				// The selection marker goes without colors
+				let mut styles = Vec::new();
+				if choice.has_styles() {
+					styles.extend([Style::default(); 2]);
+					styles.extend((start..start + item_columns).map(|i| choice.style_at(i)));
+				}
 				draw_line(
-					i + 1,
-					format!("{} {mtch}", if selection { '>' } else { ' ' }).as_str(),
+					i + first_item,
+					format!(
+						"{} {}",
+						if selection { '>' } else { ' ' },
+						clip(text, start, item_columns)
+					)
+					.as_str(),
 					selection,
+					&styles,
 				)
 			});
+		let [r, g, b] = self.config.colors.fg;
+		let cursor_color = [b, g, r];
+		let cursor_height = self.font.height.min(height);
+		// Underline the input method composition
+		let underline = (cursor_height - 1) * width;
+		let preedit = prompt_width + preedit.start.min(query_columns)
+			..prompt_width + preedit.end.min(query_columns);
+		for i in preedit.start * self.font.width..preedit.end * self.font.width {
+			let index = 4 * (underline + i);
+			canvas[index..index + 3].copy_from_slice(&cursor_color);
+		}
 		// Render the cursor
-		for i in 0..self.font.height {
-			let index = 4 * (self.picker.cursor() * self.font.width + i * width as usize);
-			let cursor_color = match THEME {
-				Mode::Dark => 0xff,
-				Mode::Light => 0x00,
-			};
-			canvas[index] = cursor_color;
-			canvas[index + 1] = cursor_color;
-			canvas[index + 2] = cursor_color;
+		if cursor < query_columns {
+			let cursor = prompt_width + cursor;
+			for i in 0..cursor_height {
+				let index = 4 * (cursor * self.font.width + i * width);
+				canvas[index..index + 3].copy_from_slice(&cursor_color);
+			}
+		}
+	}
+	/// Moves the options read so far over to the picker
+	fn read_input(&mut self) {
+		let Some(input) = &self.input else {
+			return;
+		};
+		loop {
+			match input.try_recv() {
+				Ok(option) => self.picker.push(option),
+				Err(TryRecvError::Empty) => return,
+				Err(TryRecvError::Disconnected) => break,
+			}
+		}
+		self.input = None;
+		// Reaps a finished reload command, after which its process group may
+		// no longer be ours to kill
+		if let Some(command) = &mut self.command {
+			if !matches!(command.try_wait(), Ok(None)) {
+				self.command = None;
+			}
 		}
 	}
+	/// Match counts in the style of fzf, led by a spinner while options are
+	/// still being read
+	fn info_line(&self) -> String {
+		let spinner = match self.input {
+			Some(_) => {
+				let frame = self.started.elapsed().as_millis() / SPINNER_INTERVAL.as_millis();
+				SPINNER[frame as usize % SPINNER.len()]
+			}
+			None => ' ',
+		};
+		format!(
+			"{spinner} {}/{}",
+			self.picker.match_count(),
+			self.picker.option_count()
+		)
+	}
+	/// The query line as displayed with any input method composition spliced
+	/// in at the cursor, along with the cursor column and the composed columns
+	fn query_line(&self) -> (String, usize, Range<usize>) {
+		let query = self.picker.query();
+		let cursor = self.picker.cursor();
+		match &self.preedit {
+			Some((text, preedit_cursor)) => {
+				let (left, right) = query.split_at(self.picker.cursor_byte_offset());
+				let length = text.chars().count();
+				let preedit_cursor = preedit_cursor
+					.and_then(|i| text.get(..i))
+					.map_or(length, |text| text.chars().count());
+				(
+					format!("{left}{text}{right}"),
+					cursor + preedit_cursor,
+					cursor..cursor + length,
+				)
+			}
+			None => (query.to_string(), cursor, cursor..cursor),
+		}
+	}
+	/// Area of the text cursor relative to the canvas as `(x, y, width, height)`,
+	/// used by input methods to place their popups
+	pub fn cursor_rectangle(&self) -> (i32, i32, i32, i32) {
+		let (_, cursor, _) = self.query_line();
+		let cursor = self.config.prompt.chars().count() + cursor.saturating_sub(self.query_scroll);
+		(
+			(cursor * self.font.width) as i32,
+			0,
+			1,
+			self.font.height as i32,
+		)
+	}
+	/// The query along with the cursor position in bytes, given to input
+	/// methods as the text surrounding the cursor
+	pub fn surrounding_text(&self) -> (&str, usize) {
+		(self.picker.query(), self.picker.cursor_byte_offset())
+	}
+	/// Drains the requests made to the `Window` since the last call
+	pub fn take_requests(&mut self) -> Vec<Request> {
+		std::mem::take(&mut self.requests)
+	}
+	/// What was accepted to be launched, if anything
+	pub fn launch(&self) -> Option<&Launch> {
+		self.launch.as_ref()
+	}
+	pub fn colors(&self) -> &Colors {
+		&self.config.colors
+	}
+	/// The environment to launch in
+	pub fn environment(&self) -> &Environment {
+		&self.config.env
+	}
 	pub fn running(&self) -> bool {
 		self.running
 	}
 	pub fn close(&mut self, exit_code: i32) {
 		self.running = false;
 		self.exit_code = exit_code;
+		self.stop_command();
+	}
+	/// Stops the reload command if it's still running
+	fn stop_command(&mut self) {
+		if let Some(command) = self.command.take() {
+			input::stop_command(command);
+		}
+	}
+	pub fn exit_code(&self) -> i32 {
+		self.exit_code
 	}
-	pub fn exit(&self) {
+	/// Takes what is meant for stdout
+	pub fn take_output(&mut self) -> Vec<u8> {
+		std::mem::take(&mut self.output)
+	}
+	/// Writes the output to stdout and exits with the exit code
+	pub fn exit(&mut self) {
+		let output = self.take_output();
+		let mut stdout = std::io::stdout().lock();
+		if let Err(err) = stdout.write_all(&output).and_then(|()| stdout.flush()) {
+			warn!("Failed to write the selection: {err}");
+		}
 		std::process::exit(self.exit_code);
 	}
 }
+
+impl Drop for App {
+	/// Also for pickers that never got closed, the daemon would otherwise
+	/// collect the reload commands left behind
+	fn drop(&mut self) {
+		self.stop_command();
+	}
+}
+
+/// Color of a pixel in BGR order, for a glyph with `pixel_value` drawn in `style`
+fn blend(colors: &Colors, style: Style, selection: bool, pixel_value: u8) -> [u8; 3] {
+	// Glyphs are already adjusted to the theme, undo that to get the coverage
+	let coverage = match THEME {
+		Mode::Dark => pixel_value,
+		Mode::Light => 0xff - pixel_value,
+	} as u32;
+	// The colors of styled text trade places on the selection
+	let (foreground, background) = if selection {
+		(
+			style.bg.unwrap_or(colors.selected_fg),
+			style.fg.unwrap_or(colors.selected_bg),
+		)
+	} else {
+		(style.fg.unwrap_or(colors.fg), style.bg.unwrap_or(colors.bg))
+	};
+	let channel = |i: usize| {
+		let (fg, bg) = (foreground[i] as u32, background[i] as u32);
+		((fg * coverage + bg * (0xff - coverage)) / 0xff) as u8
+	};
+	[channel(2), channel(1), channel(0)]
+}
+
+/// First character to show of an item `length` characters long, so that the
+/// character at `first_match` stays in view of `columns` despite the ellipses
+fn scroll_to(first_match: usize, length: usize, columns: usize) -> usize {
+	if length <= columns || first_match + ELLIPSIS_WIDTH < columns {
+		return 0;
+	}
+	(first_match + ELLIPSIS_WIDTH + 1)
+		.saturating_sub(columns)
+		.min(length - columns)
+}
+
+/// The characters of `text` from `start` on that fit into `columns`, with an
+/// ellipsis over the ends that are cut off
+fn clip(text: &str, start: usize, columns: usize) -> String {
+	let mut line: Vec<char> = text.chars().skip(start).take(columns).collect();
+	let ellipsis = ELLIPSIS_WIDTH.min(line.len());
+	if start > 0 {
+		line[..ellipsis].fill('.');
+	}
+	if start + columns < text.chars().count() {
+		let end = line.len();
+		line[end - ellipsis..].fill('.');
+	}
+	line.into_iter().collect()
+}
@@ -0,0 +1,377 @@
+use crate::events::{Keycode, Modifiers};
+use anyhow::{anyhow, Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Keys that are spelled out by name in key specs, in the same style as fzf
+const NAMED_KEYS: [(&str, Keycode); 28] = [
+	("enter", Keycode::Return),
+	("esc", Keycode::Escape),
+	("tab", Keycode::Tab),
+	("space", Keycode::space),
+	("bspace", Keycode::BackSpace),
+	("del", Keycode::Delete),
+	("insert", Keycode::Insert),
+	("up", Keycode::Up),
+	("down", Keycode::Down),
+	("left", Keycode::Left),
+	("right", Keycode::Right),
+	("home", Keycode::Home),
+	("end", Keycode::End),
+	("pgup", Keycode::Page_Up),
+	("pgdn", Keycode::Page_Down),
+	("f1", Keycode::F1),
+	("f2", Keycode::F2),
+	("f3", Keycode::F3),
+	("f4", Keycode::F4),
+	("f5", Keycode::F5),
+	("f6", Keycode::F6),
+	("f7", Keycode::F7),
+	("f8", Keycode::F8),
+	("f9", Keycode::F9),
+	("f10", Keycode::F10),
+	("f11", Keycode::F11),
+	("f12", Keycode::F12),
+	// Only an alias, `enter` is the canonical name
+	("return", Keycode::Return),
+];
+
+/// A key together with the modifiers held down while pressing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+	pub ctrl: bool,
+	pub alt: bool,
+	pub shift: bool,
+	pub keycode: Keycode,
+}
+
+impl Key {
+	/// Normalizes a key press so it can be compared against parsed key specs
+	pub fn from_event(modifiers: &Modifiers, keycode: Keycode) -> Self {
+		let (keycode, shift) = match keycode.key_char() {
+			// Letters are bound by their lowercase symbol, shift is tracked separately
+			Some(ch) if ch.is_ascii_alphabetic() => {
+				(Keycode::from_char(ch.to_ascii_lowercase()), modifiers.shift)
+			}
+			// For other symbols shift is already part of the symbol itself ('?' vs '/')
+			Some(ch) if ch.is_ascii_graphic() => (keycode, false),
+			_ => (keycode, modifiers.shift),
+		};
+		Self {
+			ctrl: modifiers.ctrl,
+			alt: modifiers.alt,
+			shift,
+			keycode,
+		}
+	}
+}
+
+impl FromStr for Key {
+	type Err = Error;
+	/// Parses key specs such as `ctrl-v`, `alt-enter`, `shift-insert` or `?`
+	fn from_str(spec: &str) -> Result<Self> {
+		let (mut ctrl, mut alt, mut shift) = (false, false, false);
+		let mut name = spec;
+		loop {
+			if let Some(rest) = name.strip_prefix("ctrl-") {
+				ctrl = true;
+				name = rest;
+			} else if let Some(rest) = name.strip_prefix("alt-") {
+				alt = true;
+				name = rest;
+			} else if let Some(rest) = name.strip_prefix("shift-") {
+				shift = true;
+				name = rest;
+			} else {
+				break;
+			}
+		}
+		let mut chars = name.chars();
+		let keycode = match (chars.next(), chars.next()) {
+			(Some(ch), None) if ch.is_ascii_alphabetic() => {
+				shift |= ch.is_ascii_uppercase();
+				Keycode::from_char(ch.to_ascii_lowercase())
+			}
+			(Some(ch), None) if ch.is_ascii_graphic() => Keycode::from_char(ch),
+			_ => NAMED_KEYS
+				.iter()
+				.find(|(key_name, _)| *key_name == name)
+				.map(|(_, keycode)| *keycode)
+				.ok_or_else(|| anyhow!("Unknown key: {spec}"))?,
+		};
+		Ok(Self {
+			ctrl,
+			alt,
+			shift,
+			keycode,
+		})
+	}
+}
+
+impl fmt::Display for Key {
+	/// Formats the key in its canonical spec, such that parsing it gives back
+	/// the same key. Keys that can't be bound have no spec, so they're only
+	/// given a name to show, which doesn't parse.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.ctrl {
+			f.write_str("ctrl-")?;
+		}
+		if self.alt {
+			f.write_str("alt-")?;
+		}
+		let name = NAMED_KEYS
+			.iter()
+			.find(|(_, keycode)| *keycode == self.keycode)
+			.map(|(name, _)| *name);
+		match (name, self.keycode.key_char()) {
+			// Shifted letters are spelled in uppercase
+			(None, Some(ch)) if ch.is_ascii_alphabetic() && self.shift => {
+				write!(f, "{}", ch.to_ascii_uppercase())
+			}
+			(None, Some(ch)) if ch.is_ascii_graphic() => write!(f, "{ch}"),
+			_ => {
+				if self.shift {
+					f.write_str("shift-")?;
+				}
+				match name {
+					Some(name) => f.write_str(name),
+					// Keys without a spec are at least given a recognizable name
+					None => match self.keycode.name() {
+						Some(name) => {
+							write!(f, "{}", name.trim_start_matches("XK_").to_lowercase())
+						}
+						None => write!(f, "{:#x}", self.keycode.raw()),
+					},
+				}
+			}
+		}
+	}
+}
+
+/// Parses a comma separated list of key specs, as given to `--expect`
+pub fn parse_keys(spec: &str) -> Result<Vec<Key>> {
+	spec.split(',').map(str::parse).collect()
+}
+
+/// Everything a key can be bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+	Accept,
+	/// Accept, but put the selection on the clipboard instead of stdout
+	AcceptClipboard,
+	Abort,
+	Up,
+	Down,
+	BackwardChar,
+	ForwardChar,
+	BeginningOfLine,
+	EndOfLine,
+	BackwardDeleteChar,
+	UnixWordRubout,
+	UnixLineDiscard,
+	KillLine,
+	Paste,
+	PastePrimary,
+	PreviousHistory,
+	NextHistory,
+	/// Run a command in the background, see `execute::expand` for the placeholders
+	Execute(String),
+	/// Replace the picker with a command
+	Become(String),
+	/// Replace the options with the output of a command
+	Reload(String),
+	ChangeQuery(String),
+}
+
+impl FromStr for Action {
+	type Err = Error;
+	/// Parses action names, and actions taking a command as `name(command)`
+	fn from_str(name: &str) -> Result<Self> {
+		if let Some((action, command)) =
+			name.strip_suffix(')').and_then(|name| name.split_once('('))
+		{
+			return match action {
+				"execute" => Ok(Self::Execute(command.to_string())),
+				"become" => Ok(Self::Become(command.to_string())),
+				"reload" => Ok(Self::Reload(command.to_string())),
+				"change-query" => Ok(Self::ChangeQuery(command.to_string())),
+				_ => Err(anyhow!("Unknown action: {name}")),
+			};
+		}
+		Ok(match name {
+			"accept" => Self::Accept,
+			"accept-clipboard" => Self::AcceptClipboard,
+			"abort" => Self::Abort,
+			"up" => Self::Up,
+			"down" => Self::Down,
+			"backward-char" => Self::BackwardChar,
+			"forward-char" => Self::ForwardChar,
+			"beginning-of-line" => Self::BeginningOfLine,
+			"end-of-line" => Self::EndOfLine,
+			"backward-delete-char" => Self::BackwardDeleteChar,
+			"unix-word-rubout" => Self::UnixWordRubout,
+			"unix-line-discard" => Self::UnixLineDiscard,
+			"kill-line" => Self::KillLine,
+			"paste" => Self::Paste,
+			"paste-primary" => Self::PastePrimary,
+			"previous-history" => Self::PreviousHistory,
+			"next-history" => Self::NextHistory,
+			_ => return Err(anyhow!("Unknown action: {name}")),
+		})
+	}
+}
+
+/// Maps keys, and events like the query changing, to the actions they perform
+pub struct Bindings {
+	keys: Vec<(Key, Action)>,
+	/// Performed whenever the query changes
+	change: Option<Action>,
+}
+
+impl Default for Bindings {
+	fn default() -> Self {
+		let mut bindings = Self {
+			keys: Vec::new(),
+			change: None,
+		};
+		for (spec, action) in [
+			("enter", Action::Accept),
+			("esc", Action::Abort),
+			("ctrl-c", Action::Abort),
+			("up", Action::Up),
+			("ctrl-p", Action::Up),
+			("down", Action::Down),
+			("ctrl-n", Action::Down),
+			("left", Action::BackwardChar),
+			("ctrl-b", Action::BackwardChar),
+			("right", Action::ForwardChar),
+			("ctrl-f", Action::ForwardChar),
+			("ctrl-a", Action::BeginningOfLine),
+			("ctrl-e", Action::EndOfLine),
+			("bspace", Action::BackwardDeleteChar),
+			("ctrl-w", Action::UnixWordRubout),
+			("ctrl-u", Action::UnixLineDiscard),
+			("ctrl-k", Action::KillLine),
+			("ctrl-v", Action::Paste),
+			("shift-insert", Action::PastePrimary),
+		] {
+			bindings.bind(spec.parse().unwrap(), action);
+		}
+		bindings
+	}
+}
+
+impl Bindings {
+	/// Binds `key` to `action`, replacing any previous binding of `key`
+	pub fn bind(&mut self, key: Key, action: Action) {
+		self.keys.retain(|(bound, _)| *bound != key);
+		self.keys.push((key, action));
+	}
+	/// Parses a comma separated list of `KEY:ACTION` pairs, as given to `--bind`,
+	/// where `change` in place of a key stands for the query changing.
+	/// Commas inside the parentheses of an action's command don't separate.
+	pub fn parse(&mut self, spec: &str) -> Result<()> {
+		let mut depth = 0usize;
+		let bindings = spec.split(|ch| {
+			match ch {
+				'(' => depth += 1,
+				')' => depth = depth.saturating_sub(1),
+				_ => (),
+			}
+			ch == ',' && depth == 0
+		});
+		for binding in bindings {
+			let (key, action) = binding
+				.split_once(':')
+				.ok_or_else(|| anyhow!("Invalid binding, expected KEY:ACTION: {binding}"))?;
+			match key {
+				"change" => self.change = Some(action.parse()?),
+				_ => self.bind(key.parse()?, action.parse()?),
+			}
+		}
+		Ok(())
+	}
+	pub fn get(&self, key: &Key) -> Option<&Action> {
+		self.keys
+			.iter()
+			.find(|(bound, _)| bound == key)
+			.map(|(_, action)| action)
+	}
+	/// All bound actions, including the one for the query changing
+	pub fn actions(&self) -> impl Iterator<Item = &Action> {
+		self.keys
+			.iter()
+			.map(|(_, action)| action)
+			.chain(self.change.as_ref())
+	}
+	pub fn on_change(&self) -> Option<&Action> {
+		self.change.as_ref()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn key(spec: &str) -> Key {
+		spec.parse().unwrap()
+	}
+
+	#[test]
+	fn round_trip() {
+		for spec in [
+			"a",
+			"A",
+			"?",
+			"/",
+			"enter",
+			"space",
+			"f12",
+			"ctrl-v",
+			"alt-enter",
+			"shift-insert",
+			"shift-tab",
+			"ctrl-alt-x",
+			"ctrl-alt-shift-up",
+		] {
+			assert_eq!(key(spec).to_string(), spec);
+		}
+	}
+
+	#[test]
+	fn canonical_spec() {
+		assert_eq!(key("return").to_string(), "enter");
+		assert_eq!(key("shift-a").to_string(), "A");
+		assert_eq!(key("alt-ctrl-x").to_string(), "ctrl-alt-x");
+		assert_eq!(key("shift-a"), key("A"));
+	}
+
+	#[test]
+	fn unknown_keys() {
+		assert!("".parse::<Key>().is_err());
+		assert!("ctrl-".parse::<Key>().is_err());
+		assert!("hyper-x".parse::<Key>().is_err());
+		assert!("é".parse::<Key>().is_err());
+	}
+
+	#[test]
+	fn keys_without_a_spec_are_named() {
+		let key = Key {
+			ctrl: false,
+			alt: false,
+			shift: true,
+			keycode: Keycode::ISO_Left_Tab,
+		};
+		assert_eq!(key.to_string(), "shift-iso_left_tab");
+		assert!(key.to_string().parse::<Key>().is_err());
+	}
+
+	#[test]
+	fn key_lists() {
+		assert_eq!(
+			parse_keys("ctrl-y,enter").unwrap(),
+			[key("ctrl-y"), key("enter")]
+		);
+		assert!(parse_keys("ctrl-y,nope").is_err());
+	}
+}
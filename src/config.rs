@@ -0,0 +1,521 @@
+use crate::ansi::Color;
+use crate::bindings::{self, Action, Bindings, Key};
+use crate::execute::Environment;
+use crate::picker::Choice;
+use crate::{Mode, THEME, WINDOW_SIZE};
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+
+/// Where the accepted selection is written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+	Stdout,
+	Clipboard,
+	/// Launched as an application, see `Launcher`
+	Launch,
+}
+
+/// What the options are when launching applications
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Launcher {
+	/// The applications of the `.desktop` files in the XDG data directories
+	Drun,
+	/// The executables on `PATH`, run through the shell
+	Run,
+}
+
+/// Vertical position of the window on the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+	Top,
+	Center,
+	Bottom,
+}
+
+/// Where the match counts are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Info {
+	/// On a line of its own below the query
+	Default,
+	/// At the end of the query line
+	Inline,
+	Hidden,
+}
+
+/// Criteria ordering matches with equal scores
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tiebreak {
+	/// Shorter options first
+	Length,
+	/// Matches closer to the start first
+	Begin,
+	/// Matches closer to the end first
+	End,
+	/// Options read earlier first
+	Index,
+}
+
+/// How matches are ordered
+#[derive(Debug, Clone)]
+pub struct Sorting {
+	/// Whether to sort by score at all, or keep the input order
+	pub sort: bool,
+	/// Reverse the input order
+	pub tac: bool,
+	/// Applied in order to equal scores, the input order breaks remaining ties
+	pub tiebreak: Vec<Tiebreak>,
+}
+
+/// Whether the query matches regardless of case
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+	/// Case sensitive only if the query contains uppercase letters
+	Smart,
+	Ignore,
+	Respect,
+}
+
+/// The kind of entries listed by the directory walk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+	File,
+	Directory,
+}
+
+/// How the current directory is walked when stdin is a terminal
+#[derive(Debug, Clone)]
+pub struct Walk {
+	pub entry_type: EntryType,
+	/// Include hidden files and directories
+	pub hidden: bool,
+	/// Follow symbolic links
+	pub follow: bool,
+	pub max_depth: Option<usize>,
+}
+
+/// A window dimension, either in glyphs or relative to the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Length {
+	Glyphs(u32),
+	Percent(u32),
+}
+
+/// Where the window shows up and how big it is
+#[derive(Debug, Clone)]
+pub struct Placement {
+	pub width: Length,
+	pub height: Length,
+	/// Name of the output, `None` leaves the choice to the compositor which
+	/// usually goes with the focused one
+	pub output: Option<String>,
+	pub position: Position,
+	/// Distance from the edges in logical pixels as top, right, bottom and left.
+	/// Only the edges the window is anchored to take effect.
+	pub margin: [i32; 4],
+}
+
+impl Default for Placement {
+	fn default() -> Self {
+		Self {
+			width: Length::Glyphs(WINDOW_SIZE.0 as u32),
+			height: Length::Glyphs(WINDOW_SIZE.1 as u32),
+			output: None,
+			position: Position::Center,
+			margin: [0; 4],
+		}
+	}
+}
+
+/// Colors of the text and the background, and of the selected option
+#[derive(Debug, Clone)]
+pub struct Colors {
+	pub fg: Color,
+	pub bg: Color,
+	pub selected_fg: Color,
+	pub selected_bg: Color,
+}
+
+impl Default for Colors {
+	fn default() -> Self {
+		let (fg, bg) = match THEME {
+			Mode::Dark => ([0xff; 3], [0x00; 3]),
+			Mode::Light => ([0x00; 3], [0xff; 3]),
+		};
+		Self {
+			fg,
+			bg,
+			selected_fg: bg,
+			selected_bg: fg,
+		}
+	}
+}
+
+/// Options given on the command line
+pub struct Config {
+	pub bindings: Bindings,
+	/// Keys that accept, and are reported on the first line of the output
+	pub expect: Vec<Key>,
+	/// Initial query
+	pub query: String,
+	/// Print the query ahead of the selection
+	pub print_query: bool,
+	/// Accept right away when exactly one option matches the initial query
+	pub select_1: bool,
+	/// Exit right away when no option matches the initial query
+	pub exit_0: bool,
+	pub destination: Destination,
+	/// Launch the chosen application, instead of picking from the input
+	pub launcher: Option<Launcher>,
+	/// Behave like dmenu, which takes the query for the selection when
+	/// nothing matches
+	pub dmenu: bool,
+	/// Shown by the daemon, which `become` would replace
+	pub daemon: bool,
+	/// Environment of the commands run
+	pub env: Environment,
+	pub placement: Placement,
+	pub info: Info,
+	/// Shown ahead of the query
+	pub prompt: String,
+	pub colors: Colors,
+	pub case: Case,
+	pub sorting: Sorting,
+	pub walk: Walk,
+	/// Options in the input are separated by NUL instead of newline
+	pub read0: bool,
+	/// End the output with NUL instead of newline
+	pub print0: bool,
+	/// Options are colored with ANSI escape sequences
+	pub ansi: bool,
+	/// Print the selection with its escape sequences
+	pub keep_ansi: bool,
+	/// File the accepted queries are saved to
+	pub history: Option<PathBuf>,
+	/// Maximum number of queries kept in the history
+	pub history_size: usize,
+	/// Unix socket to take commands from, see `remote::listen`
+	pub listen: Option<PathBuf>,
+	/// Store of how often and how recently options were accepted
+	pub frecency_db: Option<PathBuf>,
+	/// Separates the frecency of different pickers sharing a store
+	pub frecency_namespace: String,
+	/// How much frecency counts compared to the fuzzy score, 0 turns it off
+	pub frecency_weight: f64,
+}
+
+impl Config {
+	/// Parses the command line arguments, excluding the program name
+	pub fn from_args(mut args: impl Iterator<Item = String>, env: Environment) -> Result<Self> {
+		let mut config = Self {
+			bindings: Bindings::default(),
+			expect: Vec::new(),
+			query: String::new(),
+			print_query: false,
+			select_1: false,
+			exit_0: false,
+			destination: Destination::Stdout,
+			launcher: None,
+			dmenu: false,
+			daemon: false,
+			env,
+			placement: Placement::default(),
+			info: Info::Default,
+			prompt: String::new(),
+			colors: Colors::default(),
+			case: Case::Smart,
+			sorting: Sorting {
+				sort: true,
+				tac: false,
+				tiebreak: vec![Tiebreak::Length],
+			},
+			walk: Walk {
+				entry_type: EntryType::File,
+				hidden: false,
+				follow: false,
+				max_depth: None,
+			},
+			read0: false,
+			print0: false,
+			ansi: false,
+			keep_ansi: false,
+			history: None,
+			history_size: 1000,
+			listen: None,
+			frecency_db: None,
+			frecency_namespace: "default".to_string(),
+			frecency_weight: 10.0,
+		};
+		// Applied after the defaults that depend on other options
+		let mut bind_specs = Vec::new();
+		while let Some(arg) = args.next() {
+			// Both `--option=value` and `--option value` are accepted
+			let (option, mut inline_value) = match arg.split_once('=') {
+				Some((option, value)) if option.starts_with("--") => {
+					(option.to_string(), Some(value.to_string()))
+				}
+				_ => (arg, None),
+			};
+			let mut value = || {
+				inline_value
+					.take()
+					.or_else(|| args.next())
+					.ok_or_else(|| anyhow!("Missing value for {option}"))
+			};
+			match option.as_str() {
+				"--bind" => bind_specs.push(value()?),
+				"--query" => config.query = value()?,
+				"--print-query" => config.print_query = true,
+				"--select-1" => config.select_1 = true,
+				"--exit-0" => config.exit_0 = true,
+				"--expect" => config.expect.extend(bindings::parse_keys(&value()?)?),
+				"--clipboard" => config.destination = Destination::Clipboard,
+				"--mode" => {
+					config.launcher = match value()?.as_str() {
+						"drun" => Some(Launcher::Drun),
+						"run" => Some(Launcher::Run),
+						mode => return Err(anyhow!("Invalid mode: {mode}")),
+					};
+					config.destination = Destination::Launch;
+				}
+				"--output" => {
+					let output = value()?;
+					config.placement.output = (output != "focused").then_some(output);
+				}
+				"--position" => {
+					config.placement.position = match value()?.as_str() {
+						"top" => Position::Top,
+						"center" => Position::Center,
+						"bottom" => Position::Bottom,
+						position => return Err(anyhow!("Invalid position: {position}")),
+					}
+				}
+				"--width" => config.placement.width = parse_length(&value()?)?,
+				"--height" => config.placement.height = parse_length(&value()?)?,
+				"--info" => {
+					config.info = match value()?.as_str() {
+						"default" => Info::Default,
+						"inline" => Info::Inline,
+						"hidden" => Info::Hidden,
+						info => return Err(anyhow!("Invalid info style: {info}")),
+					}
+				}
+				"--listen" => config.listen = Some(value()?.into()),
+				"--history" => config.history = Some(value()?.into()),
+				"--history-size" => {
+					let size = value()?;
+					config.history_size = match size.parse() {
+						Ok(0) | Err(_) => return Err(anyhow!("Invalid history size: {size}")),
+						Ok(size) => size,
+					}
+				}
+				"--frecency-db" => config.frecency_db = Some(value()?.into()),
+				"--frecency-namespace" => {
+					let namespace = value()?;
+					if namespace.is_empty() || namespace.contains(['\t', '\n']) {
+						return Err(anyhow!("Invalid frecency namespace: {namespace:?}"));
+					}
+					config.frecency_namespace = namespace;
+				}
+				"--frecency-weight" => {
+					let weight = value()?;
+					config.frecency_weight = match weight.parse() {
+						Ok(weight) if weight >= 0.0 => weight,
+						_ => return Err(anyhow!("Invalid frecency weight: {weight}")),
+					}
+				}
+				"--prompt" => config.prompt = value()?,
+				"--color" => config.colors.parse(&value()?)?,
+				"-i" => config.case = Case::Ignore,
+				"+i" => config.case = Case::Respect,
+				"--dmenu" => config.parse_dmenu(&mut args)?,
+				"--no-sort" => config.sorting.sort = false,
+				"--tac" => config.sorting.tac = true,
+				"--tiebreak" => config.sorting.tiebreak = parse_tiebreak(&value()?)?,
+				"--read0" => config.read0 = true,
+				"--print0" => config.print0 = true,
+				"--ansi" => config.ansi = true,
+				"--keep-ansi" => config.keep_ansi = true,
+				"--type" => {
+					config.walk.entry_type = match value()?.as_str() {
+						"f" => EntryType::File,
+						"d" => EntryType::Directory,
+						entry_type => return Err(anyhow!("Invalid type: {entry_type}")),
+					}
+				}
+				"--hidden" => config.walk.hidden = true,
+				"--follow" => config.walk.follow = true,
+				"--max-depth" => {
+					let depth = value()?;
+					config.walk.max_depth = Some(
+						depth
+							.parse()
+							.map_err(|err| anyhow!("Invalid depth {depth}: {err}"))?,
+					);
+				}
+				"--margin" => config.placement.margin = parse_margin(&value()?)?,
+				_ => return Err(anyhow!("Unknown option: {option}")),
+			}
+		}
+		// Commands are ranked by how often and recently they were run, unless
+		// a store is given
+		if config.launcher == Some(Launcher::Run) && config.frecency_db.is_none() {
+			config.frecency_db = crate::executables::cache_dir(&config.env)
+				.map(|dir| dir.join("fzf_gui").join("run-frecency"));
+			config.frecency_namespace = "run".to_string();
+		}
+		// Like fzf, ctrl-p and ctrl-n step through the history when there is one
+		if config.history.is_some() {
+			config
+				.bindings
+				.bind("ctrl-p".parse()?, Action::PreviousHistory);
+			config.bindings.bind("ctrl-n".parse()?, Action::NextHistory);
+		}
+		for spec in bind_specs {
+			config.bindings.parse(&spec)?;
+		}
+		Ok(config)
+	}
+}
+
+impl Config {
+	/// Parses the options of dmenu following `--dmenu`, mapping them onto ours
+	fn parse_dmenu(&mut self, args: &mut impl Iterator<Item = String>) -> Result<()> {
+		self.dmenu = true;
+		// Like dmenu: at the top, without match counts and case sensitive
+		self.placement.position = Position::Top;
+		self.info = Info::Hidden;
+		self.case = Case::Respect;
+		while let Some(option) = args.next() {
+			let mut value = || {
+				args.next()
+					.ok_or_else(|| anyhow!("Missing value for {option}"))
+			};
+			match option.as_str() {
+				"-b" => self.placement.position = Position::Bottom,
+				"-i" => self.case = Case::Ignore,
+				"-p" => self.prompt = format!("{} ", value()?),
+				"-l" => {
+					let lines = value()?;
+					let lines: u32 = lines
+						.parse()
+						.map_err(|err| anyhow!("Invalid number of lines {lines}: {err}"))?;
+					// +1 for the query line
+					self.placement.height = Length::Glyphs(lines + 1);
+				}
+				// Only the built-in font is available
+				"-fn" => {
+					value()?;
+				}
+				"-nb" => self.colors.bg = parse_color(&value()?)?,
+				"-nf" => self.colors.fg = parse_color(&value()?)?,
+				"-sb" => self.colors.selected_bg = parse_color(&value()?)?,
+				"-sf" => self.colors.selected_fg = parse_color(&value()?)?,
+				_ => return Err(anyhow!("Unknown dmenu option: {option}")),
+			}
+		}
+		Ok(())
+	}
+}
+
+impl Colors {
+	/// Parses a comma separated list of `NAME:#RRGGBB` pairs, as given to
+	/// `--color`, where `fg+` and `bg+` are the colors of the selection
+	fn parse(&mut self, spec: &str) -> Result<()> {
+		for pair in spec.split(',') {
+			let (name, color) = pair
+				.split_once(':')
+				.ok_or_else(|| anyhow!("Invalid color, expected NAME:COLOR: {pair}"))?;
+			let color = parse_color(color)?;
+			match name {
+				"fg" => self.fg = color,
+				"bg" => self.bg = color,
+				"fg+" => self.selected_fg = color,
+				"bg+" => self.selected_bg = color,
+				_ => return Err(anyhow!("Unknown color name: {name}")),
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Parses colors given as `#RRGGBB` or `#RGB`
+fn parse_color(spec: &str) -> Result<Color> {
+	let invalid = || anyhow!("Invalid color, expected #RRGGBB: {spec}");
+	let hex = spec.strip_prefix('#').ok_or_else(invalid)?;
+	let digits = hex
+		.chars()
+		.map(|digit| digit.to_digit(16).map(|digit| digit as u8))
+		.collect::<Option<Vec<u8>>>()
+		.ok_or_else(invalid)?;
+	match digits[..] {
+		[r, g, b] => Ok([r * 0x11, g * 0x11, b * 0x11]),
+		[r1, r0, g1, g0, b1, b0] => Ok([r1 << 4 | r0, g1 << 4 | g0, b1 << 4 | b0]),
+		_ => Err(invalid()),
+	}
+}
+
+/// Parses margins given as `ALL`, `VERTICAL,HORIZONTAL` or `TOP,RIGHT,BOTTOM,LEFT`
+fn parse_margin(spec: &str) -> Result<[i32; 4]> {
+	let values = spec
+		.split(',')
+		.map(|i| i.trim().parse())
+		.collect::<Result<Vec<i32>, _>>()
+		.map_err(|err| anyhow!("Invalid margin {spec}: {err}"))?;
+	Ok(match values[..] {
+		[all] => [all; 4],
+		[vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+		[top, right, bottom, left] => [top, right, bottom, left],
+		_ => return Err(anyhow!("Invalid margin {spec}: expected 1, 2 or 4 values")),
+	})
+}
+
+impl Config {
+	/// Separator of the options in the input
+	pub fn input_delimiter(&self) -> u8 {
+		if self.read0 {
+			b'\0'
+		} else {
+			b'\n'
+		}
+	}
+	/// Turns options read from the input into choices
+	pub fn choice_parser(&self) -> fn(Vec<u8>) -> Choice {
+		if self.ansi {
+			Choice::from_ansi
+		} else {
+			Choice::from_bytes
+		}
+	}
+}
+
+/// Parses a comma separated list of tiebreak criteria, as given to `--tiebreak`
+fn parse_tiebreak(spec: &str) -> Result<Vec<Tiebreak>> {
+	let mut tiebreak = Vec::new();
+	for name in spec.split(',') {
+		let criterion = match name {
+			"length" => Tiebreak::Length,
+			"begin" => Tiebreak::Begin,
+			"end" => Tiebreak::End,
+			"index" => Tiebreak::Index,
+			_ => return Err(anyhow!("Unknown tiebreak criterion: {name}")),
+		};
+		if tiebreak.contains(&criterion) {
+			return Err(anyhow!("Duplicate tiebreak criterion: {name}"));
+		}
+		tiebreak.push(criterion);
+	}
+	Ok(tiebreak)
+}
+
+/// Parses lengths given as a number of glyphs or as `N%` of the output
+fn parse_length(spec: &str) -> Result<Length> {
+	let length = match spec.strip_suffix('%') {
+		Some(percent) => Length::Percent(percent.parse()?),
+		None => Length::Glyphs(spec.parse()?),
+	};
+	match length {
+		Length::Glyphs(0) | Length::Percent(0) => Err(anyhow!("Length can't be zero: {spec}")),
+		Length::Percent(101..) => Err(anyhow!("Length can't exceed 100%: {spec}")),
+		_ => Ok(length),
+	}
+}
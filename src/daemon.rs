@@ -0,0 +1,241 @@
+use crate::app::App;
+use crate::bindings::Action;
+use crate::config::{Config, Destination};
+use crate::execute::Environment;
+use crate::fonts::{Font, Fonts};
+use crate::input::{self, Source};
+use crate::remote;
+use crate::window::Window;
+use anyhow::{anyhow, Context, Result};
+use atty::Stream;
+use log::warn;
+use serde_json::{json, Map, Value};
+use smithay_client_toolkit::reexports::calloop::EventLoop;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use wayland_client::Connection;
+
+/// How long a client gets to send its request
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The window pickers are shown in along with its event loop, created for the
+/// first client and reused for those after it, so the globals of the
+/// compositor are only bound once
+type Display = (Window, EventLoop<'static, Window>);
+
+/// Where the daemon listens, `FZF_GUI_SOCKET` or `fzf_gui.sock` in the
+/// runtime directory
+fn socket_path() -> Result<PathBuf> {
+	if let Some(path) = std::env::var_os("FZF_GUI_SOCKET") {
+		return Ok(path.into());
+	}
+	let runtime = std::env::var_os("XDG_RUNTIME_DIR")
+		.context("Neither FZF_GUI_SOCKET nor XDG_RUNTIME_DIR is set")?;
+	Ok(Path::new(&runtime).join("fzf_gui.sock"))
+}
+
+/// Serves pickers for clients on the socket one after another, keeping the
+/// font at every scale it was needed at and the Wayland connection around in
+/// between.
+///
+/// A client sends a line of JSON with its `args`, `cwd`, `env` and whether
+/// `stdin` follows, followed by the options if so. It's answered with a line
+/// of JSON with the `exit_code` and any `error`, followed by the output.
+pub fn serve(font: Font) -> Result<()> {
+	let conn = Connection::connect_to_env().context("Failed to connect to Wayland")?;
+	let path = socket_path()?;
+	let listener = remote::bind(&path)?;
+	let fonts = Fonts::new(font);
+	let mut display = None;
+	for stream in listener.incoming() {
+		let stream = match stream {
+			Ok(stream) => stream,
+			Err(err) => {
+				warn!("Failed to accept a connection: {err}");
+				continue;
+			}
+		};
+		if let Err(err) = session(stream, &conn, &fonts, &mut display) {
+			warn!("Failed to serve a client: {err:#}");
+		}
+		// Every session after this one would fail the same way
+		if conn.flush().is_err() {
+			let _ = std::fs::remove_file(&path);
+			return Err(anyhow!("Lost the connection to Wayland"));
+		}
+	}
+	Ok(())
+}
+
+/// Serves a single client
+fn session(
+	stream: UnixStream,
+	conn: &Connection,
+	fonts: &Fonts,
+	display: &mut Option<Display>,
+) -> Result<()> {
+	// A client that never sends its request would keep everyone else waiting
+	stream.set_read_timeout(Some(HEADER_TIMEOUT))?;
+	let mut reader = BufReader::new(stream.try_clone()?);
+	let mut header = String::new();
+	reader
+		.read_line(&mut header)
+		.context("Failed to read the request")?;
+	// Options on stdin take as long as whatever produces them
+	stream.set_read_timeout(None)?;
+	let header: Value = serde_json::from_str(&header).context("Malformed request")?;
+	let (exit_code, output, error) = match pick(&header, reader, conn, fonts, display) {
+		Ok((exit_code, output)) => (exit_code, output, None),
+		Err(err) => (2, Vec::new(), Some(format!("{err:#}"))),
+	};
+	let mut stream = stream;
+	let reply = json!({ "exit_code": exit_code, "error": error });
+	writeln!(stream, "{reply}")?;
+	stream.write_all(&output)?;
+	// Also stops the reading of options the client may still be sending
+	stream.shutdown(Shutdown::Both)?;
+	Ok(())
+}
+
+/// Shows the picker the client asked for, returning the exit code and output
+fn pick(
+	header: &Value,
+	reader: BufReader<UnixStream>,
+	conn: &Connection,
+	fonts: &Fonts,
+	display: &mut Option<Display>,
+) -> Result<(i32, Vec<u8>)> {
+	let args: Vec<String> = header["args"]
+		.as_array()
+		.context("Missing the arguments")?
+		.iter()
+		.filter_map(|arg| arg.as_str().map(String::from))
+		.collect();
+	let cwd = header["cwd"]
+		.as_str()
+		.map(PathBuf::from)
+		.filter(|cwd| cwd.is_absolute())
+		.context("Missing an absolute working directory")?;
+	// Commands run for the client see its environment
+	let env = header["env"]
+		.as_object()
+		.context("Missing the environment")?
+		.iter()
+		.filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+		.collect();
+	let mut config = Config::from_args(args.into_iter(), Environment::client(env, cwd.clone()))?;
+	// Relative to the client rather than to us
+	for path in [
+		&mut config.history,
+		&mut config.listen,
+		&mut config.frecency_db,
+	]
+	.into_iter()
+	.flatten()
+	{
+		*path = cwd.join(&*path);
+	}
+	// Also refused when it comes in through --listen
+	config.daemon = true;
+	if matches!(config.destination, Destination::Clipboard) {
+		return Err(anyhow!("--clipboard isn't supported by the daemon"));
+	}
+	if config
+		.bindings
+		.actions()
+		.any(|action| matches!(action, Action::Become(_)))
+	{
+		return Err(anyhow!("become isn't supported by the daemon"));
+	}
+
+	let pipe: Option<Box<dyn Read + Send>> = if header["stdin"].as_bool().unwrap_or(false) {
+		Some(Box::new(reader))
+	} else {
+		None
+	};
+	let (source, entries) = match config.launcher {
+		Some(_) => crate::source(&config),
+		None => {
+			let default_command = config.env.var("FZF_DEFAULT_COMMAND");
+			(Source::new(pipe, default_command), Vec::new())
+		}
+	};
+	let options = input::start(source, &config);
+	let placement = config.placement.clone();
+	let listen = config.listen.clone();
+	let mut app = App::new(fonts.clone(), options, config);
+	if app.finish_early() {
+		return Ok((app.exit_code(), app.take_output()));
+	}
+	let (mut window, mut event_loop) = match display.take() {
+		Some((mut window, event_loop)) => {
+			window.app = app;
+			(window, event_loop)
+		}
+		None => crate::connect(conn, app)?,
+	};
+	let shown = crate::show(
+		&mut window,
+		&mut event_loop,
+		conn,
+		placement,
+		listen.as_deref(),
+	)
+	.and_then(|()| crate::launch(&mut window, &mut event_loop, &entries));
+	window.close();
+	let result = (window.app.exit_code(), window.app.take_output());
+	// Kept for the next client, even if this one failed
+	*display = Some((window, event_loop));
+	shown.map(|()| result)
+}
+
+/// Has the daemon show a picker for `args`, passing on stdin and the
+/// environment, then prints its output and exits with its exit code
+pub fn connect(args: impl Iterator<Item = String>) -> Result<()> {
+	let path = socket_path()?;
+	let stream = UnixStream::connect(&path)
+		.with_context(|| format!("Failed to connect to the daemon at {}", path.display()))?;
+	let cwd = std::env::current_dir().context("Failed to get the working directory")?;
+	let cwd = cwd
+		.to_str()
+		.context("The working directory isn't valid UTF-8")?;
+	let env: Map<String, Value> = std::env::vars_os()
+		.filter_map(|(key, value)| {
+			Some((key.into_string().ok()?, value.into_string().ok()?.into()))
+		})
+		.collect();
+	let stdin = !atty::is(Stream::Stdin);
+	let header = json!({
+		"args": args.collect::<Vec<_>>(),
+		"cwd": cwd,
+		"env": env,
+		"stdin": stdin,
+	});
+	let mut writer = stream.try_clone()?;
+	writeln!(writer, "{header}")?;
+	// Sent in the background, as the picker may be done before stdin is
+	thread::spawn(move || {
+		if stdin {
+			let _ = io::copy(&mut io::stdin().lock(), &mut writer);
+		}
+		let _ = writer.shutdown(Shutdown::Write);
+	});
+
+	let mut reader = BufReader::new(stream);
+	let mut reply = String::new();
+	reader.read_line(&mut reply)?;
+	let reply: Value = serde_json::from_str(&reply).context("The daemon didn't answer")?;
+	let mut output = Vec::new();
+	reader.read_to_end(&mut output)?;
+	let mut stdout = io::stdout().lock();
+	stdout.write_all(&output)?;
+	stdout.flush()?;
+	if let Some(error) = reply["error"].as_str() {
+		eprintln!("fzf_gui: {error}");
+	}
+	std::process::exit(reply["exit_code"].as_i64().unwrap_or(2) as i32);
+}
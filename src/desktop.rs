@@ -0,0 +1,401 @@
+use crate::execute::{self, Environment};
+use crate::picker::Choice;
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// An application as described by a `.desktop` file
+pub struct Entry {
+	/// Localized name, displayed and matched against
+	name: String,
+	/// Localized keywords, matched against without being displayed
+	keywords: Vec<String>,
+	exec: String,
+	icon: Option<String>,
+	/// Whether the application runs in a terminal
+	terminal: bool,
+	/// Working directory to launch the application in
+	working_dir: Option<String>,
+	/// The `.desktop` file, for the `%k` field code
+	path: PathBuf,
+}
+
+/// Lists the applications shown in menus, from the `applications` directories
+/// of the XDG data directories, sorted by name
+pub fn entries(env: &Environment) -> Vec<Entry> {
+	let locales = locales(env);
+	let desktops: Vec<String> = env
+		.var("XDG_CURRENT_DESKTOP")
+		.unwrap_or_default()
+		.split(':')
+		.filter(|desktop| !desktop.is_empty())
+		.map(String::from)
+		.collect();
+	// Entries earlier in the data directories override those with the same ID
+	// later on, even if it's to hide them
+	let mut seen = HashSet::new();
+	let mut entries = Vec::new();
+	for dir in data_dirs(env) {
+		for (id, path) in desktop_files(&dir.join("applications")) {
+			if !seen.insert(id) {
+				continue;
+			}
+			match fs::read_to_string(&path) {
+				Ok(content) => entries.extend(parse(&content, path, &locales, &desktops)),
+				Err(err) => warn!("Failed to read {}: {err}", path.display()),
+			}
+		}
+	}
+	entries.sort_by_cached_key(|entry| entry.name.to_lowercase());
+	entries
+}
+
+/// `XDG_DATA_HOME` followed by `XDG_DATA_DIRS`, with the defaults of the base
+/// directory specification
+fn data_dirs(env: &Environment) -> Vec<PathBuf> {
+	let non_empty = |var: &str| env.var_os(var).filter(|value| !value.is_empty());
+	let home = non_empty("XDG_DATA_HOME")
+		.map(PathBuf::from)
+		.or_else(|| non_empty("HOME").map(|home| Path::new(&home).join(".local/share")));
+	let dirs = non_empty("XDG_DATA_DIRS")
+		.and_then(|dirs| dirs.into_string().ok())
+		.unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+	home.into_iter()
+		.chain(
+			dirs.split(':')
+				.filter(|dir| !dir.is_empty())
+				.map(PathBuf::from),
+		)
+		.collect()
+}
+
+/// The `.desktop` files below `dir` along with their desktop file IDs, the
+/// path relative to `dir` with slashes replaced by dashes
+fn desktop_files(dir: &Path) -> Vec<(String, PathBuf)> {
+	let mut files = Vec::new();
+	let mut dirs = vec![dir.to_path_buf()];
+	while let Some(current) = dirs.pop() {
+		// Missing directories are common and nothing to warn about
+		let Ok(children) = fs::read_dir(&current) else {
+			continue;
+		};
+		for child in children.flatten() {
+			let path = child.path();
+			// Follows symbolic links, as these are common in data directories
+			if path.is_dir() {
+				dirs.push(path);
+			} else if path
+				.extension()
+				.is_some_and(|extension| extension == "desktop")
+			{
+				let Some(id) = path.strip_prefix(dir).ok().and_then(Path::to_str) else {
+					continue;
+				};
+				files.push((id.replace('/', "-"), path));
+			}
+		}
+	}
+	files
+}
+
+/// Locales to look up localized values with, most specific first, going by
+/// `LC_ALL`, `LC_MESSAGES` and `LANG` as in `lang_COUNTRY.ENCODING@MODIFIER`
+fn locales(env: &Environment) -> Vec<String> {
+	let Some(locale) = ["LC_ALL", "LC_MESSAGES", "LANG"]
+		.into_iter()
+		.filter_map(|var| env.var(var))
+		.find(|locale| !locale.is_empty())
+	else {
+		return Vec::new();
+	};
+	let (locale, modifier) = match locale.split_once('@') {
+		Some((locale, modifier)) => (locale, Some(modifier)),
+		None => (locale.as_str(), None),
+	};
+	let locale = locale.split('.').next().unwrap_or_default();
+	let (lang, country) = match locale.split_once('_') {
+		Some((lang, country)) => (lang, Some(country)),
+		None => (locale, None),
+	};
+	let mut locales = Vec::new();
+	if let (Some(country), Some(modifier)) = (country, modifier) {
+		locales.push(format!("{lang}_{country}@{modifier}"));
+	}
+	if let Some(country) = country {
+		locales.push(format!("{lang}_{country}"));
+	}
+	if let Some(modifier) = modifier {
+		locales.push(format!("{lang}@{modifier}"));
+	}
+	locales.push(lang.to_string());
+	locales
+}
+
+/// Reads the `Desktop Entry` group of a `.desktop` file, skipping entries
+/// that aren't applications meant to be shown in menus on `desktops`
+fn parse(content: &str, path: PathBuf, locales: &[String], desktops: &[String]) -> Option<Entry> {
+	let mut values = HashMap::new();
+	let mut in_entry = false;
+	for line in content.lines() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		if line.starts_with('[') {
+			in_entry = line == "[Desktop Entry]";
+			continue;
+		}
+		if let Some((key, value)) = line.split_once('=').filter(|_| in_entry) {
+			// The first occurrence of a key counts
+			values.entry(key.trim()).or_insert(value.trim());
+		}
+	}
+	let get = |key: &str| values.get(key).copied();
+	let localized = |key: &str| {
+		locales
+			.iter()
+			.find_map(|locale| get(&format!("{key}[{locale}]")))
+			.or_else(|| get(key))
+	};
+	let shown_on = |key: &str| {
+		get(key).map(|list| {
+			split_list(list)
+				.iter()
+				.any(|desktop| desktops.contains(desktop))
+		})
+	};
+	if get("Type") != Some("Application")
+		|| get("NoDisplay") == Some("true")
+		|| get("Hidden") == Some("true")
+		|| shown_on("OnlyShowIn") == Some(false)
+		|| shown_on("NotShowIn") == Some(true)
+	{
+		return None;
+	}
+	Some(Entry {
+		name: unescape(localized("Name")?),
+		keywords: localized("Keywords").map(split_list).unwrap_or_default(),
+		exec: unescape(get("Exec")?),
+		icon: get("Icon").map(unescape),
+		terminal: get("Terminal") == Some("true"),
+		working_dir: get("Path").map(unescape).filter(|dir| !dir.is_empty()),
+		path,
+	})
+}
+
+/// Replaces the escape sequences of string values
+fn unescape(value: &str) -> String {
+	let mut unescaped = String::with_capacity(value.len());
+	let mut chars = value.chars();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			unescaped.push(ch);
+			continue;
+		}
+		match chars.next() {
+			Some('s') => unescaped.push(' '),
+			Some('n') => unescaped.push('\n'),
+			Some('t') => unescaped.push('\t'),
+			Some('r') => unescaped.push('\r'),
+			Some(ch) => unescaped.push(ch),
+			None => unescaped.push('\\'),
+		}
+	}
+	unescaped
+}
+
+/// Splits a list value on the semicolons that aren't escaped
+fn split_list(value: &str) -> Vec<String> {
+	let mut items = Vec::new();
+	let mut item = String::new();
+	let mut chars = value.chars();
+	while let Some(ch) = chars.next() {
+		match ch {
+			';' => items.push(unescape(&std::mem::take(&mut item))),
+			'\\' => {
+				match chars.next() {
+					Some(';') => item.push(';'),
+					// Left for `unescape`
+					Some(ch) => item.extend(['\\', ch]),
+					None => item.push('\\'),
+				}
+			}
+			_ => item.push(ch),
+		}
+	}
+	items.push(unescape(&item));
+	items.retain(|item| !item.is_empty());
+	items
+}
+
+/// Splits the `Exec` value into arguments, which are separated by spaces
+/// unless double quoted. Inside quotes, backslashes escape the next character.
+fn split_exec(exec: &str) -> Result<Vec<String>> {
+	let mut args = Vec::new();
+	let mut arg: Option<String> = None;
+	let mut quoted = false;
+	let mut chars = exec.chars();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'"' => {
+				quoted = !quoted;
+				arg.get_or_insert_default();
+			}
+			'\\' if quoted => {
+				let escaped = chars.next().context("Unterminated escape")?;
+				arg.get_or_insert_default().push(escaped);
+			}
+			' ' | '\t' if !quoted => args.extend(arg.take()),
+			_ => arg.get_or_insert_default().push(ch),
+		}
+	}
+	if quoted {
+		return Err(anyhow!("Unterminated quote"));
+	}
+	args.extend(arg);
+	Ok(args)
+}
+
+impl Entry {
+	pub fn choice(&self) -> Choice {
+		Choice::with_keywords(self.name.clone(), &self.keywords)
+	}
+	/// The command line of the entry with the field codes expanded. No files
+	/// or URLs are passed, so the codes for them are dropped.
+	fn command_line(&self, env: &Environment) -> Result<Vec<String>> {
+		let args = split_exec(&self.exec)
+			.with_context(|| format!("Invalid Exec in {}", self.path.display()))?;
+		let mut expanded = Vec::with_capacity(args.len());
+		for arg in args {
+			match arg.as_str() {
+				"%f" | "%F" | "%u" | "%U" => continue,
+				"%i" => {
+					if let Some(icon) = &self.icon {
+						expanded.extend(["--icon".to_string(), icon.clone()]);
+					}
+					continue;
+				}
+				_ => (),
+			}
+			let mut result = String::with_capacity(arg.len());
+			let mut chars = arg.chars();
+			while let Some(ch) = chars.next() {
+				if ch != '%' {
+					result.push(ch);
+					continue;
+				}
+				match chars.next() {
+					Some('%') => result.push('%'),
+					Some('c') => result.push_str(&self.name),
+					Some('k') => result.push_str(&self.path.to_string_lossy()),
+					// Deprecated codes and those for files are removed
+					_ => (),
+				}
+			}
+			expanded.push(result);
+		}
+		if self.terminal {
+			let terminal = env.var("TERMINAL").unwrap_or_else(|| "xterm".to_string());
+			expanded.splice(0..0, [terminal, "-e".to_string()]);
+		}
+		Ok(expanded)
+	}
+	/// Starts the application detached from us in `env`, passing on `token`
+	/// so the compositor can give it the focus
+	pub fn launch(&self, token: Option<&str>, env: &Environment) -> Result<()> {
+		let args = self.command_line(env)?;
+		let (program, args) = args
+			.split_first()
+			.with_context(|| format!("Empty Exec in {}", self.path.display()))?;
+		let mut command = Command::new(program);
+		env.apply(&mut command).args(args);
+		if let Some(dir) = &self.working_dir {
+			command.current_dir(dir);
+		}
+		execute::detach(command, token).with_context(|| format!("Failed to launch {}", self.name))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entry(content: &str, locales: &[&str]) -> Option<Entry> {
+		let locales: Vec<String> = locales.iter().map(|locale| locale.to_string()).collect();
+		let desktops = ["GNOME".to_string()];
+		parse(content, "/apps/test.desktop".into(), &locales, &desktops)
+	}
+
+	fn exec(exec: &str) -> Vec<String> {
+		let content =
+			format!("[Desktop Entry]\nType=Application\nName=Test\nIcon=test-icon\nExec={exec}");
+		entry(&content, &[])
+			.unwrap()
+			.command_line(&Environment::inherited())
+			.unwrap()
+	}
+
+	#[test]
+	fn splits_exec() {
+		assert_eq!(split_exec("app  -a\tb").unwrap(), ["app", "-a", "b"]);
+		assert_eq!(
+			split_exec(r#""my app" "a \"b\" \\ \$c" """#).unwrap(),
+			["my app", r#"a "b" \ $c"#, ""]
+		);
+		assert_eq!(split_exec(r#"a"b c"d"#).unwrap(), ["ab cd"]);
+		assert!(split_exec(r#"app "open"#).is_err());
+		assert!(split_exec(r#"app "\"#).is_err());
+	}
+
+	#[test]
+	fn expands_field_codes() {
+		assert_eq!(exec("app %f %U --x"), ["app", "--x"]);
+		assert_eq!(exec("app %i"), ["app", "--icon", "test-icon"]);
+		assert_eq!(
+			exec("app 100%% --name=%c %k"),
+			["app", "100%", "--name=Test", "/apps/test.desktop"]
+		);
+		assert_eq!(exec(r#"app "--a=%F" %d"#), ["app", "--a=", ""]);
+	}
+
+	#[test]
+	fn unescapes_values() {
+		assert_eq!(unescape(r"a\sb\tc\nd\\e\;"), "a b\tc\nd\\e;");
+		assert_eq!(unescape(r"end\"), r"end\");
+		assert_eq!(split_list(r"a;b\;c;;d\s;"), ["a", "b;c", "d "]);
+	}
+
+	#[test]
+	fn picks_the_most_specific_locale() {
+		let content = "[Desktop Entry]\nType=Application\nExec=app\nName=Plain\nName[de]=German\nName[de_AT]=Austrian\nKeywords[de]=eins;zwei;";
+		let name = |locales: &[&str]| entry(content, locales).unwrap().name;
+		assert_eq!(name(&["de_AT", "de"]), "Austrian");
+		assert_eq!(name(&["de_CH", "de"]), "German");
+		assert_eq!(name(&["fr_FR", "fr"]), "Plain");
+		assert_eq!(name(&[]), "Plain");
+		assert_eq!(entry(content, &["de"]).unwrap().keywords, ["eins", "zwei"]);
+	}
+
+	#[test]
+	fn skips_entries_not_shown() {
+		let base = "[Desktop Entry]\nType=Application\nName=App\nExec=app\n";
+		assert!(entry(base, &[]).is_some());
+		assert!(entry(&format!("{base}NoDisplay=true"), &[]).is_none());
+		assert!(entry(&format!("{base}Hidden=true"), &[]).is_none());
+		assert!(entry(&format!("{base}OnlyShowIn=KDE;"), &[]).is_none());
+		assert!(entry(&format!("{base}OnlyShowIn=KDE;GNOME;"), &[]).is_some());
+		assert!(entry(&format!("{base}NotShowIn=GNOME;"), &[]).is_none());
+		assert!(entry(&base.replace("Application", "Link"), &[]).is_none());
+	}
+
+	#[test]
+	fn only_reads_the_desktop_entry_group() {
+		let content = "[Desktop Entry]\nType=Application\nName=App\nExec=app\n[Desktop Action new]\nName=New Window\nExec=app --new";
+		let entry = entry(content, &[]).unwrap();
+		assert_eq!(entry.name, "App");
+		assert_eq!(entry.exec, "app");
+	}
+}
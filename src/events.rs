@@ -2,6 +2,8 @@ pub use smithay_client_toolkit::seat::keyboard::{Keysym, Modifiers};
 
 pub use Keysym as Keycode;
 
+use crate::bindings::Action;
+
 #[derive(Debug)]
 pub enum Event {
 	Focused(bool),
@@ -10,4 +12,42 @@ pub enum Event {
 		keycode: Keycode,
 		utf8: Option<String>,
 	},
+	MiddleClick,
+	/// Text received from one of the clipboards
+	Paste(String),
+	/// In-progress composition from an input method, an empty `text` ends it.
+	/// `cursor` is a byte offset into `text`, if the input method places one.
+	Preedit {
+		text: String,
+		cursor: Option<usize>,
+	},
+	/// Text composed by an input method
+	Commit(String),
+	/// Request from an input method to delete bytes around the cursor
+	DeleteSurrounding {
+		before: usize,
+		after: usize,
+	},
+	/// An action requested from outside, over the `--listen` socket
+	Action(Action),
+}
+
+/// The two selections Wayland exposes to clients
+#[derive(Debug, Clone, Copy)]
+pub enum Clipboard {
+	/// The regular `Ctrl+C`/`Ctrl+V` clipboard
+	Regular,
+	/// The primary selection (highlighted text, pasted with the middle mouse button)
+	Primary,
+}
+
+/// Things the `App` asks of the `Window` in response to an `Event`
+#[derive(Debug)]
+pub enum Request {
+	Paste(Clipboard),
+	/// Offer the text on the regular clipboard
+	Copy(String),
+	/// Ask the compositor for a token to launch an application with, so the
+	/// application can take the focus
+	ActivationToken,
 }
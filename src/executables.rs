@@ -0,0 +1,253 @@
+use crate::execute::Environment;
+use anyhow::{Context, Result};
+use log::warn;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Executables found in a directory of `PATH`
+struct Listing {
+	/// Modification time of the directory in nanoseconds since the Unix
+	/// epoch, any change to its entries changes it
+	modified: u128,
+	names: Vec<String>,
+}
+
+/// Lists the names of the executables on `PATH`, sorted and without
+/// duplicates.
+///
+/// Directories are only read again when they were modified since they were
+/// cached. The cache is a text file with a `dir\tMODIFIED\tPATH` line per
+/// directory, each followed by an `exe\tNAME` line per executable in it.
+pub fn executables(env: &Environment) -> Vec<String> {
+	let cache = cache_dir(env).map(|dir| dir.join("fzf_gui").join("run"));
+	let mut cached = match cache.as_deref().map(read_cache).transpose() {
+		Ok(cached) => cached.unwrap_or_default(),
+		Err(err) => {
+			warn!("Failed to read the cache: {err:#}");
+			HashMap::new()
+		}
+	};
+	let mut listings = Vec::new();
+	let mut changed = false;
+	for dir in path_dirs(env) {
+		// Directories that can't be read are skipped, like missing ones
+		let Some(modified) = modified(&dir) else {
+			changed |= cached.remove(&dir).is_some();
+			continue;
+		};
+		let listing = match cached.remove(&dir) {
+			Some(listing) if listing.modified == modified => listing,
+			_ => {
+				changed = true;
+				Listing {
+					modified,
+					names: list(&dir),
+				}
+			}
+		};
+		listings.push((dir, listing));
+	}
+	// Directories no longer on `PATH` are dropped as well
+	changed |= !cached.is_empty();
+	if let Some(cache) = cache.filter(|_| changed) {
+		if let Err(err) = write_cache(&cache, &listings) {
+			warn!("Failed to update the cache: {err:#}");
+		}
+	}
+	let mut names: Vec<String> = listings
+		.into_iter()
+		.flat_map(|(_, listing)| listing.names)
+		.collect();
+	names.sort_unstable();
+	names.dedup();
+	names
+}
+
+/// `XDG_CACHE_HOME`, or `.cache` in the home directory
+pub fn cache_dir(env: &Environment) -> Option<PathBuf> {
+	let non_empty = |var: &str| env.var_os(var).filter(|value| !value.is_empty());
+	non_empty("XDG_CACHE_HOME")
+		.map(PathBuf::from)
+		.or_else(|| non_empty("HOME").map(|home| Path::new(&home).join(".cache")))
+}
+
+/// The absolute directories of `PATH` in order, without duplicates
+fn path_dirs(env: &Environment) -> Vec<PathBuf> {
+	let mut dirs: Vec<PathBuf> = Vec::new();
+	for dir in std::env::split_paths(&env.var_os("PATH").unwrap_or_default()) {
+		// Relative directories depend on where we happen to be
+		if dir.is_absolute() && !dirs.contains(&dir) {
+			dirs.push(dir);
+		}
+	}
+	dirs
+}
+
+fn modified(dir: &Path) -> Option<u128> {
+	let modified = fs::metadata(dir).and_then(|metadata| metadata.modified());
+	Some(modified.ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+/// Names of the executable files in `dir`, following symbolic links. Names
+/// that don't fit on a line of the cache are skipped.
+fn list(dir: &Path) -> Vec<String> {
+	let Ok(children) = fs::read_dir(dir) else {
+		return Vec::new();
+	};
+	children
+		.flatten()
+		.filter(|child| {
+			fs::metadata(child.path()).is_ok_and(|metadata| {
+				metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+			})
+		})
+		.filter_map(|child| child.file_name().into_string().ok())
+		.filter(|name| !name.contains(['\t', '\n']))
+		.collect()
+}
+
+/// Reads the cache, skipping malformed lines. A missing cache is empty.
+fn read_cache(path: &Path) -> Result<HashMap<PathBuf, Listing>> {
+	let content = match fs::read_to_string(path) {
+		Ok(content) => content,
+		Err(err) if err.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+		Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+	};
+	let mut listings: Vec<(PathBuf, Listing)> = Vec::new();
+	// Names following a malformed directory line are skipped along with it
+	let mut valid = false;
+	for line in content.lines() {
+		match line.split_once('\t') {
+			Some(("dir", rest)) => {
+				let listing = rest.split_once('\t').and_then(|(modified, dir)| {
+					let modified = modified.parse().ok()?;
+					Some((
+						dir.into(),
+						Listing {
+							modified,
+							names: Vec::new(),
+						},
+					))
+				});
+				valid = listing.is_some();
+				listings.extend(listing);
+			}
+			Some(("exe", name)) if valid => {
+				if let Some((_, listing)) = listings.last_mut() {
+					listing.names.push(name.to_string());
+				}
+			}
+			_ => (),
+		}
+	}
+	Ok(listings.into_iter().collect())
+}
+
+fn write_cache(path: &Path, listings: &[(PathBuf, Listing)]) -> Result<()> {
+	let mut content = String::new();
+	for (dir, listing) in listings {
+		// Such a path wouldn't be read back the same
+		let Some(dir) = dir.to_str().filter(|dir| !dir.contains('\n')) else {
+			continue;
+		};
+		content.push_str(&format!("dir\t{}\t{dir}\n", listing.modified));
+		for name in &listing.names {
+			content.push_str(&format!("exe\t{name}\n"));
+		}
+	}
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+	}
+	// Pickers started at the same time never see a partially written cache
+	let mut temporary = path.as_os_str().to_owned();
+	temporary.push(format!(".{}.tmp", std::process::id()));
+	fs::write(&temporary, content)
+		.with_context(|| format!("Failed to write {}", Path::new(&temporary).display()))?;
+	fs::rename(&temporary, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// An empty directory of its own for `name`
+	fn temporary(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("fzf_gui-{}-{name}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	fn listing(modified: u128, names: &[&str]) -> Listing {
+		Listing {
+			modified,
+			names: names.iter().map(|name| name.to_string()).collect(),
+		}
+	}
+
+	#[test]
+	fn cache_round_trip() {
+		let dir = temporary("round-trip");
+		let path = dir.join("run");
+		let listings = [
+			("/bin".into(), listing(1, &["ls", "cat"])),
+			("/usr/bin".into(), listing(2, &[])),
+			// Not written at all
+			("/new\nline".into(), listing(3, &["x"])),
+		];
+		write_cache(&path, &listings).unwrap();
+		let cached = read_cache(&path).unwrap();
+		assert_eq!(cached.len(), 2);
+		assert_eq!(cached[Path::new("/bin")].modified, 1);
+		assert_eq!(cached[Path::new("/bin")].names, ["ls", "cat"]);
+		assert!(cached[Path::new("/usr/bin")].names.is_empty());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn malformed_directories_are_skipped_with_their_names() {
+		let dir = temporary("malformed");
+		let path = dir.join("run");
+		let content = "exe\torphan\ndir\tsoon\t/bin\nexe\tls\ndir\t5\t/sbin\nexe\tinit\ngarbage\n";
+		fs::write(&path, content).unwrap();
+		let cached = read_cache(&path).unwrap();
+		assert_eq!(cached.len(), 1);
+		assert_eq!(cached[Path::new("/sbin")].names, ["init"]);
+		assert!(read_cache(&dir.join("missing")).unwrap().is_empty());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn modified_directories_are_listed_again() {
+		let root = temporary("modified");
+		let (bin, cache) = (root.join("bin"), root.join("cache"));
+		fs::create_dir(&bin).unwrap();
+		let program = bin.join("program");
+		fs::write(&program, "").unwrap();
+		fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+		fs::write(bin.join("data"), "").unwrap();
+		let vars = HashMap::from([
+			("PATH".to_string(), bin.to_str().unwrap().to_string()),
+			(
+				"XDG_CACHE_HOME".to_string(),
+				cache.to_str().unwrap().to_string(),
+			),
+		]);
+		let env = Environment::client(vars, root.clone());
+		let path = cache.join("fzf_gui").join("run");
+
+		// Up to date listings are taken as they are
+		let current = modified(&bin).unwrap();
+		write_cache(&path, &[(bin.clone(), listing(current, &["cached"]))]).unwrap();
+		assert_eq!(executables(&env), ["cached"]);
+		// Outdated ones are replaced, along with the cache
+		write_cache(&path, &[(bin.clone(), listing(current - 1, &["cached"]))]).unwrap();
+		assert_eq!(executables(&env), ["program"]);
+		assert_eq!(read_cache(&path).unwrap()[&bin].names, ["program"]);
+		fs::remove_dir_all(&root).unwrap();
+	}
+}
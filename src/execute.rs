@@ -0,0 +1,225 @@
+use anyhow::Result;
+use atty::Stream;
+use log::warn;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::{env, thread};
+
+/// The environment and working directory of the commands we run, also
+/// consulted for the variables looked up on their behalf. The daemon uses
+/// those of its client, as changing its own would race with the threads still
+/// running.
+#[derive(Clone)]
+pub struct Environment {
+	/// `None` passes on our own
+	vars: Option<HashMap<String, String>>,
+	/// Absolute, `None` keeps our own
+	dir: Option<PathBuf>,
+}
+
+impl Environment {
+	/// Our own environment
+	pub fn inherited() -> Self {
+		Self {
+			vars: None,
+			dir: None,
+		}
+	}
+	/// Exactly `vars` in the absolute directory `dir`, in place of our own
+	pub fn client(vars: HashMap<String, String>, dir: PathBuf) -> Self {
+		Self {
+			vars: Some(vars),
+			dir: Some(dir),
+		}
+	}
+	/// The working directory, `.` for our own
+	pub fn dir(&self) -> &Path {
+		self.dir.as_deref().unwrap_or(Path::new("."))
+	}
+	pub fn var(&self, key: &str) -> Option<String> {
+		match &self.vars {
+			Some(vars) => vars.get(key).cloned(),
+			None => env::var(key).ok(),
+		}
+	}
+	pub fn var_os(&self, key: &str) -> Option<OsString> {
+		match &self.vars {
+			Some(vars) => vars.get(key).map(OsString::from),
+			None => env::var_os(key),
+		}
+	}
+	/// Has `command` run in this environment
+	pub fn apply<'a>(&self, command: &'a mut Command) -> &'a mut Command {
+		if let Some(vars) = &self.vars {
+			command.env_clear().envs(vars);
+		}
+		if let Some(dir) = &self.dir {
+			command.current_dir(dir);
+		}
+		command
+	}
+}
+
+/// What placeholders in commands are replaced with
+pub struct Placeholders<'a> {
+	/// The current selection, for `{}`. Also used for the marked options of
+	/// `{+}`, as options can't be marked.
+	pub selection: Option<&'a str>,
+	/// The query, for `{q}`
+	pub query: &'a str,
+	/// Index of the current selection in the input, for `{n}`
+	pub index: Option<usize>,
+}
+
+/// Replaces the placeholders in `template` with their shell quoted values,
+/// braces that aren't placeholders are left as they are. Like with fzf, there
+/// is no command if it refers to the selection and there is none.
+pub fn expand(template: &str, placeholders: &Placeholders) -> Option<String> {
+	let mut command = String::with_capacity(template.len());
+	let mut rest = template;
+	while let Some(start) = rest.find('{') {
+		command.push_str(&rest[..start]);
+		rest = &rest[start..];
+		let (value, length) = if rest.starts_with("{}") {
+			(quote(placeholders.selection?), 2)
+		} else if rest.starts_with("{q}") {
+			(quote(placeholders.query), 3)
+		} else if rest.starts_with("{+}") {
+			// Would be space separated words, if options could be marked
+			(quote(placeholders.selection?), 3)
+		} else if rest.starts_with("{n}") {
+			(quote(&placeholders.index?.to_string()), 3)
+		} else {
+			("{".to_string(), 1)
+		};
+		command.push_str(&value);
+		rest = &rest[length..];
+	}
+	command.push_str(rest);
+	Some(command)
+}
+
+/// Quotes `text` as a single word for the shell
+pub fn quote(text: &str) -> String {
+	format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Runs `command` through the shell in the background. Its output goes to
+/// stderr, so it doesn't end up with the selection on stdout.
+pub fn spawn(command: &str, env: &Environment) -> Result<()> {
+	let stderr = io::stderr().as_fd().try_clone_to_owned()?;
+	let mut child = env
+		.apply(&mut Command::new("sh"))
+		.args(["-c", command])
+		.stdin(Stdio::null())
+		.stdout(stderr)
+		.spawn()?;
+	// Reaped on a thread of its own, so the event loop keeps going meanwhile
+	let command = command.to_string();
+	thread::spawn(move || match child.wait() {
+		Ok(status) if !status.success() => warn!("{command:?} exited with {status}"),
+		Ok(_) => (),
+		Err(err) => warn!("Failed to wait for {command:?}: {err}"),
+	});
+	Ok(())
+}
+
+/// Starts `command` detached from us, passing on the activation `token` so
+/// the compositor can give it the focus
+pub fn detach(mut command: Command, token: Option<&str>) -> io::Result<()> {
+	command
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::null());
+	if let Some(token) = token {
+		command
+			.env("XDG_ACTIVATION_TOKEN", token)
+			.env("DESKTOP_STARTUP_ID", token);
+	}
+	// SAFETY: setsid is async-signal-safe. In a session of its own, the command
+	// outlives us and the terminal we may have been started from.
+	unsafe {
+		command.pre_exec(|| {
+			libc::setsid();
+			Ok(())
+		});
+	}
+	let mut child = command.spawn()?;
+	// Reaped on a thread of its own, for when we stick around as a daemon
+	thread::spawn(move || child.wait());
+	Ok(())
+}
+
+/// Replaces this process with `command` run through the shell, only returns
+/// if that failed
+pub fn become_command(command: &str) -> io::Error {
+	let mut shell = Command::new("sh");
+	shell.args(["-c", command]);
+	// Our stdin may be the pipe the options came through, which is spent by
+	// now. Like fzf, the command gets the terminal instead, if there is one.
+	if !atty::is(Stream::Stdin) {
+		if let Ok(tty) = File::open("/dev/tty") {
+			shell.stdin(tty);
+		}
+	}
+	shell.exec()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SELECTED: Placeholders = Placeholders {
+		selection: Some("it's here"),
+		query: "a b",
+		index: Some(3),
+	};
+
+	const NOTHING: Placeholders = Placeholders {
+		selection: None,
+		query: "",
+		index: None,
+	};
+
+	#[test]
+	fn quotes_single_quotes() {
+		assert_eq!(quote("plain"), "'plain'");
+		assert_eq!(quote("it's"), r"'it'\''s'");
+		assert_eq!(quote("''"), r"''\'''\'''");
+		assert_eq!(quote(""), "''");
+	}
+
+	#[test]
+	fn expands_placeholders() {
+		assert_eq!(
+			expand("echo {} {q} {n} {+}", &SELECTED).unwrap(),
+			r"echo 'it'\''s here' 'a b' '3' 'it'\''s here'"
+		);
+	}
+
+	#[test]
+	fn leaves_other_braces() {
+		assert_eq!(
+			expand("awk '{print $1}' {x} {", &SELECTED).unwrap(),
+			"awk '{print $1}' {x} {"
+		);
+	}
+
+	#[test]
+	fn query_without_a_selection() {
+		assert_eq!(expand("rg {q}", &NOTHING).unwrap(), "rg ''");
+	}
+
+	#[test]
+	fn nothing_to_run_without_a_selection() {
+		assert_eq!(expand("echo {}", &NOTHING), None);
+		assert_eq!(expand("echo {+}", &NOTHING), None);
+		assert_eq!(expand("echo {n}", &NOTHING), None);
+	}
+}
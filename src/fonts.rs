@@ -1,11 +1,17 @@
-use crate::{Mode, THEME};
+use crate::{Mode, FONT_ATLAS, THEME};
 use anyhow::{anyhow, Error, Result};
 use resize::{px::Gray, Pixel::Gray8, Type::Triangle};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
+#[derive(Clone)]
 pub struct Font {
 	pub width: usize,
 	pub height: usize,
 	pub glyphs: [[Vec<u8>; 32]; 3],
+	/// Outlined box standing in for characters the atlas doesn't cover
+	replacement: Vec<u8>,
 }
 
 impl Font {
@@ -25,8 +31,7 @@ impl Font {
 		let mut glyphs_original: [[Vec<Gray<u8>>; 32]; 3] = Default::default();
 		for (j, row) in glyphs_original.iter_mut().enumerate() {
 			for (i, glyph) in row.iter_mut().enumerate() {
-				let top_left = i as usize * glyph_original_size.0
-					+ j as usize * glyph_original_size.1 * image_width;
+				let top_left = i * glyph_original_size.0 + j * glyph_original_size.1 * image_width;
 				for j in 0..glyph_original_size.1 {
 					for i in 0..glyph_original_size.0 {
 						let pixel_value = match THEME {
@@ -60,6 +65,7 @@ impl Font {
 			width: glyph_new_size.0,
 			height: glyph_new_size.1,
 			glyphs,
+			replacement: Self::replacement(glyph_new_size.0, glyph_new_size.1),
 		})
 	}
 
@@ -71,6 +77,30 @@ impl Font {
 		Some(&self.glyphs[(symbol_ascii >> 5) - 1][symbol_ascii & 0x1f])
 	}
 
+	/// The glyph for `symbol`, or a box if the atlas has none for it
+	pub fn glyph_or_box(&self, symbol: char) -> &[u8] {
+		self.get_glyph(symbol).unwrap_or(&self.replacement)
+	}
+
+	/// Draws the outline of a box spanning the height of lowercase letters
+	fn replacement(width: usize, height: usize) -> Vec<u8> {
+		let (ink, paper) = match THEME {
+			Mode::Dark => (0xff, 0),
+			Mode::Light => (0, 0xff),
+		};
+		let (left, right) = (width / 8, width - 1 - width / 8);
+		let (top, bottom) = (height / 3, height - 1 - height / 6);
+		let mut glyph = vec![paper; width * height];
+		for j in top..=bottom {
+			for i in left..=right {
+				if i == left || i == right || j == top || j == bottom {
+					glyph[i + j * width] = ink;
+				}
+			}
+		}
+		glyph
+	}
+
 	fn parse_pbm(bytes: &[u8]) -> Result<(usize, usize, Vec<u8>)> {
 		let mut i = 0;
 		let mut bitmap_offset = 0;
@@ -133,3 +163,36 @@ impl Font {
 		Ok((result.0?, result.1?, result.2?))
 	}
 }
+
+/// The font atlas rasterized at each size asked for so far. Clones share the
+/// sizes, so the pickers of the daemon don't redo them for every scale.
+#[derive(Clone)]
+pub struct Fonts {
+	base: Font,
+	scaled: Rc<RefCell<HashMap<usize, Font>>>,
+}
+
+impl Fonts {
+	pub fn new(base: Font) -> Self {
+		Self {
+			base,
+			scaled: Rc::default(),
+		}
+	}
+	/// The font at the size it was loaded at
+	pub fn base(&self) -> Font {
+		self.base.clone()
+	}
+	/// The font `height` pixels tall, rasterized only the first time
+	pub fn sized(&self, height: usize) -> Result<Font> {
+		if height == self.base.height {
+			return Ok(self.base());
+		}
+		if let Some(font) = self.scaled.borrow().get(&height) {
+			return Ok(font.clone());
+		}
+		let font = Font::from_pbm(FONT_ATLAS, height)?;
+		self.scaled.borrow_mut().insert(height, font.clone());
+		Ok(font)
+	}
+}
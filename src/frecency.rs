@@ -0,0 +1,227 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+/// Entries not chosen for this long are dropped from the store
+const MAX_AGE: u64 = 90 * DAY;
+
+/// Counts how often and how recently options were accepted, so they can be
+/// ranked higher the next time.
+///
+/// The store is a text file with one `NAMESPACE\tCOUNT\tLAST_USED\tITEM` line
+/// per entry, shared between all namespaces. Backslashes, tabs and line
+/// breaks in the namespace and item are escaped as in C. Writers take an
+/// exclusive lock on a `.lock` file next to it and replace the store as a
+/// whole.
+pub struct Frecency {
+	path: PathBuf,
+	namespace: String,
+}
+
+struct Entry {
+	namespace: String,
+	count: u32,
+	/// Seconds since the Unix epoch
+	last_used: u64,
+	item: String,
+}
+
+impl Frecency {
+	pub fn new(path: PathBuf, namespace: String) -> Self {
+		Self { path, namespace }
+	}
+	/// Frecency of each item in our namespace, the count weighted by how
+	/// recently it was chosen
+	pub fn scores(&self) -> Result<HashMap<String, f64>> {
+		let _lock = self.lock(libc::LOCK_SH)?;
+		let now = now();
+		Ok(read_entries(&self.path)?
+			.into_iter()
+			.filter(|entry| entry.namespace == self.namespace)
+			.map(|entry| {
+				let age = now.saturating_sub(entry.last_used);
+				let recency = match age {
+					_ if age < HOUR => 4.0,
+					_ if age < DAY => 2.0,
+					_ if age < WEEK => 0.5,
+					_ => 0.25,
+				};
+				(entry.item, entry.count as f64 * recency)
+			})
+			.collect())
+	}
+	/// Records that `item` was chosen just now
+	pub fn record(&self, item: &str) -> Result<()> {
+		let _lock = self.lock(libc::LOCK_EX)?;
+		let now = now();
+		let mut entries = read_entries(&self.path)?;
+		entries.retain(|entry| now.saturating_sub(entry.last_used) < MAX_AGE);
+		match entries
+			.iter_mut()
+			.find(|entry| entry.namespace == self.namespace && entry.item == item)
+		{
+			Some(entry) => {
+				entry.count = entry.count.saturating_add(1);
+				entry.last_used = now;
+			}
+			None => entries.push(Entry {
+				namespace: self.namespace.clone(),
+				count: 1,
+				last_used: now,
+				item: item.to_string(),
+			}),
+		}
+		let content: String = entries
+			.iter()
+			.map(|entry| {
+				format!(
+					"{}\t{}\t{}\t{}\n",
+					escape(&entry.namespace),
+					entry.count,
+					entry.last_used,
+					escape(&entry.item)
+				)
+			})
+			.collect();
+		// Readers never see a partially written store
+		let temporary = with_suffix(&self.path, ".tmp");
+		std::fs::write(&temporary, content)
+			.with_context(|| format!("Failed to write {}", temporary.display()))?;
+		std::fs::rename(&temporary, &self.path)
+			.with_context(|| format!("Failed to replace {}", self.path.display()))?;
+		Ok(())
+	}
+	/// Locks the store until the returned file is dropped
+	fn lock(&self, operation: libc::c_int) -> Result<File> {
+		let path = with_suffix(&self.path, ".lock");
+		let file = OpenOptions::new()
+			.create(true)
+			.truncate(false)
+			.write(true)
+			.open(&path)
+			.with_context(|| format!("Failed to open {}", path.display()))?;
+		// SAFETY: The file descriptor stays open for as long as `file` lives
+		if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+			return Err(std::io::Error::last_os_error())
+				.with_context(|| format!("Failed to lock {}", path.display()));
+		}
+		Ok(file)
+	}
+}
+
+/// Reads the store, skipping malformed lines. A missing store has no entries.
+fn read_entries(path: &Path) -> Result<Vec<Entry>> {
+	let content = match std::fs::read_to_string(path) {
+		Ok(content) => content,
+		Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+	};
+	Ok(content
+		.lines()
+		.filter_map(|line| {
+			let mut fields = line.splitn(4, '\t');
+			Some(Entry {
+				namespace: unescape(fields.next()?),
+				count: fields.next()?.parse().ok()?,
+				last_used: fields.next()?.parse().ok()?,
+				item: unescape(fields.next()?),
+			})
+		})
+		.collect())
+}
+
+/// Escapes the characters that would end a field or a line early
+fn escape(field: &str) -> String {
+	let mut escaped = String::with_capacity(field.len());
+	for ch in field.chars() {
+		match ch {
+			'\\' => escaped.push_str("\\\\"),
+			'\t' => escaped.push_str("\\t"),
+			'\n' => escaped.push_str("\\n"),
+			'\r' => escaped.push_str("\\r"),
+			_ => escaped.push(ch),
+		}
+	}
+	escaped
+}
+
+/// Reverses `escape`, leaving unknown escape sequences as they are
+fn unescape(field: &str) -> String {
+	let mut unescaped = String::with_capacity(field.len());
+	let mut chars = field.chars();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			unescaped.push(ch);
+			continue;
+		}
+		match chars.next() {
+			Some('\\') => unescaped.push('\\'),
+			Some('t') => unescaped.push('\t'),
+			Some('n') => unescaped.push('\n'),
+			Some('r') => unescaped.push('\r'),
+			Some(ch) => unescaped.extend(['\\', ch]),
+			None => unescaped.push('\\'),
+		}
+	}
+	unescaped
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+	let mut path = path.as_os_str().to_owned();
+	path.push(suffix);
+	path.into()
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn escape_round_trip() {
+		for field in [
+			"plain",
+			"tab\there",
+			"two\nlines\r\n",
+			"back\\slash",
+			"\\t",
+			"",
+		] {
+			let escaped = escape(field);
+			assert!(!escaped.contains(['\t', '\n', '\r']));
+			assert_eq!(unescape(&escaped), field);
+		}
+	}
+
+	#[test]
+	fn unknown_escapes_are_kept() {
+		assert_eq!(unescape("a\\qb"), "a\\qb");
+		assert_eq!(unescape("end\\"), "end\\");
+	}
+
+	#[test]
+	fn store_keeps_special_items() {
+		let path = std::env::temp_dir().join(format!("fzf_gui-{}-frecency", std::process::id()));
+		let frecency = Frecency::new(path.clone(), "name\tspace".to_string());
+		frecency.record("two\nlines").unwrap();
+		frecency.record("two\nlines").unwrap();
+		frecency.record("tab\there").unwrap();
+		let scores = frecency.scores().unwrap();
+		assert_eq!(scores.len(), 2);
+		assert!(scores["two\nlines"] > scores["tab\there"]);
+		std::fs::remove_file(&path).unwrap();
+		std::fs::remove_file(with_suffix(&path, ".lock")).unwrap();
+	}
+}
@@ -0,0 +1,69 @@
+use anyhow::Result;
+use log::warn;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Previously accepted queries, kept in a file with one query per line
+pub struct History {
+	path: PathBuf,
+	entries: Vec<String>,
+	max_size: usize,
+	/// Index of the entry being shown, `entries.len()` stands for the query
+	/// being edited
+	position: usize,
+	/// The query being edited, kept while stepping through the history
+	draft: String,
+}
+
+impl History {
+	/// Reads the history from `path`, a missing file is an empty history
+	pub fn load(path: PathBuf, max_size: usize) -> Self {
+		let entries: Vec<String> = match std::fs::read_to_string(&path) {
+			Ok(content) => content.lines().map(str::to_string).collect(),
+			Err(err) => {
+				if err.kind() != ErrorKind::NotFound {
+					warn!("Failed to read the history from {}: {err}", path.display());
+				}
+				Vec::new()
+			}
+		};
+		Self {
+			path,
+			position: entries.len(),
+			entries,
+			max_size,
+			draft: String::new(),
+		}
+	}
+	/// Steps back to the previous entry, `query` is restored when stepping
+	/// forward past the newest one
+	pub fn previous(&mut self, query: &str) -> Option<&str> {
+		if self.position == 0 {
+			return None;
+		}
+		if self.position == self.entries.len() {
+			self.draft = query.to_string();
+		}
+		self.position -= 1;
+		Some(&self.entries[self.position])
+	}
+	pub fn next(&mut self) -> Option<&str> {
+		if self.position >= self.entries.len() {
+			return None;
+		}
+		self.position += 1;
+		Some(self.entries.get(self.position).unwrap_or(&self.draft))
+	}
+	/// Appends `query` and writes the history back, dropping earlier
+	/// occurrences of `query` and the oldest entries beyond the maximum size
+	pub fn save(&mut self, query: &str) -> Result<()> {
+		self.entries.retain(|entry| entry != query);
+		self.entries.push(query.to_string());
+		let excess = self.entries.len().saturating_sub(self.max_size);
+		self.entries.drain(..excess);
+		let mut content = self.entries.join("\n");
+		content.push('\n');
+		std::fs::write(&self.path, content)?;
+		Ok(())
+	}
+}
@@ -0,0 +1,209 @@
+use crate::config::{Config, EntryType, Walk};
+use crate::execute::Environment;
+use crate::picker::Choice;
+use anyhow::{Context, Result};
+use atty::Stream;
+use ignore::{WalkBuilder, WalkState};
+use log::warn;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// Where the options come from
+pub enum Source {
+	/// Records read from a pipe, usually stdin
+	Pipe(Box<dyn Read + Send>),
+	/// Lines of output of a shell command
+	Command(String),
+	/// The files below the current directory
+	Walk,
+	/// Options known up front
+	Choices(Vec<Choice>),
+}
+
+impl Source {
+	/// Picks the source like fzf: the pipe, or when there's none because stdin
+	/// is a terminal, the default command if set and otherwise a directory walk
+	pub fn new(pipe: Option<Box<dyn Read + Send>>, default_command: Option<String>) -> Self {
+		match (pipe, default_command) {
+			(Some(pipe), _) => Self::Pipe(pipe),
+			(None, Some(command)) if !command.trim().is_empty() => Self::Command(command),
+			(None, _) => Self::Walk,
+		}
+	}
+	/// The source of this process, going by stdin and `FZF_DEFAULT_COMMAND`
+	pub fn from_env() -> Self {
+		let pipe: Option<Box<dyn Read + Send>> = if atty::is(Stream::Stdin) {
+			None
+		} else {
+			Some(Box::new(std::io::stdin()))
+		};
+		Self::new(pipe, std::env::var("FZF_DEFAULT_COMMAND").ok())
+	}
+}
+
+/// Reads the options from `source` on a thread of its own, so the window shows
+/// up right away
+pub fn start(source: Source, config: &Config) -> Receiver<Choice> {
+	let (sender, receiver) = mpsc::channel();
+	let walk = config.walk.clone();
+	let delimiter = config.input_delimiter();
+	let parse = config.choice_parser();
+	let env = config.env.clone();
+	thread::spawn(move || read_options(source, sender, &walk, delimiter, parse, &env));
+	receiver
+}
+
+/// Sends the options, separated by `delimiter` in the input and turned into
+/// choices by `parse`, until the source runs out or the receiving end is gone
+fn read_options(
+	source: Source,
+	sender: Sender<Choice>,
+	walk: &Walk,
+	delimiter: u8,
+	parse: fn(Vec<u8>) -> Choice,
+	env: &Environment,
+) {
+	match source {
+		Source::Pipe(pipe) => read_records(pipe, delimiter, parse, &sender),
+		Source::Command(command) => run_command(&command, delimiter, parse, env, &sender),
+		Source::Walk => walk_directory(walk, env.dir(), &sender),
+		Source::Choices(choices) => {
+			for choice in choices {
+				if sender.send(choice).is_err() {
+					return;
+				}
+			}
+		}
+	}
+}
+
+fn read_records(
+	source: impl Read,
+	delimiter: u8,
+	parse: fn(Vec<u8>) -> Choice,
+	sender: &Sender<Choice>,
+) {
+	for record in BufReader::new(source).split(delimiter) {
+		match record {
+			Ok(record) => {
+				if sender.send(parse(record)).is_err() {
+					return;
+				}
+			}
+			// Interruptions are retried by `split`, anything else would keep
+			// failing the same way
+			Err(err) => {
+				warn!("Failed to read the options: {err}");
+				break;
+			}
+		}
+	}
+}
+
+/// Runs `command` through the shell, reading its output as options
+fn run_command(
+	command: &str,
+	delimiter: u8,
+	parse: fn(Vec<u8>) -> Choice,
+	env: &Environment,
+	sender: &Sender<Choice>,
+) {
+	let (mut child, options) = match start_command(command, delimiter, parse, env) {
+		Ok(started) => started,
+		Err(err) => {
+			warn!("{err:#}");
+			return;
+		}
+	};
+	for choice in &options {
+		if sender.send(choice).is_err() {
+			break;
+		}
+	}
+	// Nobody is reading anymore if we got here early, which is the usual way
+	// for the command to learn it can stop
+	drop(options);
+	let _ = child.wait();
+}
+
+/// Starts `command` through the shell, its output is read on a thread of its
+/// own. The command leads a process group of its own, so `stop_command` gets
+/// rid of everything it started.
+pub fn start_command(
+	command: &str,
+	delimiter: u8,
+	parse: fn(Vec<u8>) -> Choice,
+	env: &Environment,
+) -> Result<(Child, Receiver<Choice>)> {
+	let mut child = env
+		.apply(&mut Command::new("sh"))
+		.args(["-c", command])
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.process_group(0)
+		.spawn()
+		.with_context(|| format!("Failed to run {command:?}"))?;
+	let stdout = child
+		.stdout
+		.take()
+		.context("Missing the command's output")?;
+	let (sender, receiver) = mpsc::channel();
+	thread::spawn(move || read_records(stdout, delimiter, parse, &sender));
+	Ok((child, receiver))
+}
+
+/// Kills the process group of a command from `start_command` and reaps it.
+/// Killing the shell alone would leave the pipeline it started running.
+pub fn stop_command(mut child: Child) {
+	// SAFETY: Not yet reaped, the shell's ID can't have been reused for another
+	// group. Fails if the group is gone already, which is just as good.
+	unsafe {
+		libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+	}
+	let _ = child.wait();
+}
+
+/// Lists the paths below `root` relative to it in parallel, skipping those
+/// excluded by `.gitignore` and `.ignore` files
+fn walk_directory(walk: &Walk, root: &Path, sender: &Sender<Choice>) {
+	WalkBuilder::new(root)
+		.hidden(!walk.hidden)
+		.follow_links(walk.follow)
+		.max_depth(walk.max_depth)
+		// Respect `.gitignore` even outside of git repositories, as fzf does
+		.require_git(false)
+		.build_parallel()
+		.run(|| {
+			let sender = sender.clone();
+			Box::new(move |entry| {
+				let Ok(entry) = entry else {
+					return WalkState::Continue;
+				};
+				// Skip the current directory itself
+				if entry.depth() == 0 {
+					return WalkState::Continue;
+				}
+				let wanted = entry
+					.file_type()
+					.is_some_and(|file_type| match walk.entry_type {
+						EntryType::File => !file_type.is_dir(),
+						EntryType::Directory => file_type.is_dir(),
+					});
+				if !wanted {
+					return WalkState::Continue;
+				}
+				let path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+				// Paths aren't necessarily valid UTF-8, the choice keeps them intact
+				let path = path.as_os_str().as_bytes().to_vec();
+				match sender.send(Choice::from_bytes(path)) {
+					Ok(()) => WalkState::Continue,
+					Err(_) => WalkState::Quit,
+				}
+			})
+		});
+}
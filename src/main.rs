@@ -1,18 +1,40 @@
+mod ansi;
 mod app;
+mod bindings;
+mod config;
+mod daemon;
+mod desktop;
 mod events;
+mod executables;
+mod execute;
 mod fonts;
+mod frecency;
+mod history;
+mod input;
 mod picker;
+mod remote;
 mod window;
-use app::App;
-use atty::Stream;
-use fonts::Font;
+use app::{App, Launch};
+use config::{Config, Launcher, Placement};
+use execute::Environment;
+use fonts::{Font, Fonts};
 use window::Window;
 
-use log::{debug, log_enabled, Level};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, log_enabled, warn, Level};
+use picker::Choice;
+use smithay_client_toolkit::reexports::{
+	calloop::EventLoop, calloop_wayland_source::WaylandSource,
+};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use wayland_client::Connection;
 
 /// The height of the glyphs in pixels
 const FONT_SIZE: usize = 30;
-/// Size of the window in number of glyphs
+/// See [`Font::from_pbm`] for the format
+const FONT_ATLAS: &[u8] = include_bytes!("res/font_atlas.pbm");
+/// Default size of the window in number of glyphs
 const WINDOW_SIZE: (usize, usize) = (80, 20);
 
 pub enum Mode {
@@ -25,25 +47,75 @@ pub const THEME: Mode = Mode::Dark;
 fn main() {
 	env_logger::init();
 
-	let mut options = Vec::new();
-
-	if atty::is(Stream::Stdin) {
-		let dir_reader = std::fs::read_dir(".").unwrap();
-		for i in dir_reader {
-			if let Ok(Ok(dir)) = i.map(|dir| dir.file_name().into_string()) {
-				options.push(dir);
-			}
+	let mut args = std::env::args();
+	// Run as `dmenu`, like through a symlink, takes the options of dmenu
+	let program = args.next().map(PathBuf::from);
+	let dmenu = program.as_deref().and_then(Path::file_name) == Some("dmenu".as_ref());
+	let mut args = dmenu
+		.then(|| "--dmenu".to_string())
+		.into_iter()
+		.chain(args)
+		.peekable();
+	let result = match args.peek().map(String::as_str) {
+		Some("--daemon") => load_font().and_then(daemon::serve),
+		Some("--client") => daemon::connect(args.skip(1)),
+		_ => {
+			// Errors exit with 2, like fzf
+			let config = Config::from_args(args, Environment::inherited()).unwrap_or_else(|err| {
+				eprintln!("fzf_gui: {err}");
+				std::process::exit(2);
+			});
+			run(config)
 		}
-	} else {
-		let stdin = std::io::stdin();
-		for line in stdin.lines() {
-			if let Ok(line) = line {
-				options.push(line);
-			}
+	};
+	if let Err(err) = result {
+		eprintln!("fzf_gui: {err:#}");
+		std::process::exit(2);
+	}
+}
+
+fn run(config: Config) -> Result<()> {
+	let (source, entries) = source(&config);
+	let options = input::start(source, &config);
+	let font = load_font()?;
+	// We don't draw immediately, the configure will notify us when to first draw.
+	let placement = config.placement.clone();
+	let listen = config.listen.clone();
+	let mut app = App::new(Fonts::new(font), options, config);
+	if app.finish_early() {
+		app.exit();
+	}
+	let conn = Connection::connect_to_env().context("Failed to connect to Wayland")?;
+	let (mut window, mut event_loop) = connect(&conn, app)?;
+	show(
+		&mut window,
+		&mut event_loop,
+		&conn,
+		placement,
+		listen.as_deref(),
+	)?;
+	launch(&mut window, &mut event_loop, &entries)?;
+
+	if window.owns_clipboard() {
+		window.hide();
+		conn.flush().context("Lost the connection to Wayland")?;
+		detach(&mut window);
+		// Clipboard content is served by the client owning it, so stick around
+		// until some other client takes over
+		while window.owns_clipboard() {
+			event_loop
+				.dispatch(None, &mut window)
+				.context("Lost the connection to Wayland")?;
 		}
+		std::process::exit(0);
 	}
 
-	let font = Font::from_pbm(include_bytes!("res/font_atlas.pbm"), FONT_SIZE).unwrap();
+	window.app.exit();
+	Ok(())
+}
+
+fn load_font() -> Result<Font> {
+	let font = Font::from_pbm(FONT_ATLAS, FONT_SIZE).context("Failed to load the font atlas")?;
 
 	if log_enabled!(Level::Debug) {
 		for ch in ' '..='~' {
@@ -63,22 +135,125 @@ fn main() {
 			debug!("Symbol: {ch}");
 			debug!("\n{buf}");
 		}
-	} // We don't draw immediately, the configure will notify us when to first draw.
-	let (mut window, mut event_queue) = Window::new(
-		(WINDOW_SIZE.0 * FONT_SIZE / 2) as u32,
-		// +2 for rendering the top and bottom borders (1px each)
-		(WINDOW_SIZE.1 * FONT_SIZE) as u32 + 2,
-		App::new(font, options),
-	);
-
-	loop {
-		event_queue.blocking_dispatch(&mut window).unwrap();
-
-		if !window.app.running() {
-			debug!("exiting example");
-			break;
+	}
+	Ok(font)
+}
+
+/// Where the options of `config` come from, along with the applications
+/// they stand for when launching
+fn source(config: &Config) -> (input::Source, Vec<desktop::Entry>) {
+	match config.launcher {
+		Some(Launcher::Drun) => {
+			let entries = desktop::entries(&config.env);
+			let choices = entries.iter().map(desktop::Entry::choice).collect();
+			(input::Source::Choices(choices), entries)
+		}
+		Some(Launcher::Run) => {
+			let choices = executables::executables(&config.env)
+				.into_iter()
+				.map(Choice::from)
+				.collect();
+			(input::Source::Choices(choices), Vec::new())
+		}
+		None => (input::Source::from_env(), Vec::new()),
+	}
+}
+
+/// Launches what was accepted in `window`, if anything, once the compositor
+/// handed out the activation token for it
+fn launch(
+	window: &mut Window,
+	event_loop: &mut EventLoop<'static, Window>,
+	entries: &[desktop::Entry],
+) -> Result<()> {
+	if window.app.launch().is_none() {
+		return Ok(());
+	}
+	while window.activation_pending() {
+		event_loop
+			.dispatch(None, window)
+			.context("Lost the connection to Wayland")?;
+	}
+	window.hide();
+	let token = window.take_activation_token();
+	let env = window.app.environment();
+	match window.app.launch() {
+		Some(Launch::Option(index)) => match entries.get(*index) {
+			Some(entry) => entry.launch(token.as_deref(), env),
+			None => Ok(()),
+		},
+		Some(Launch::Command(command)) => {
+			let mut shell = Command::new("sh");
+			env.apply(&mut shell).args(["-c", command]);
+			execute::detach(shell, token.as_deref())
+				.with_context(|| format!("Failed to run {command:?}"))
 		}
+		None => Ok(()),
 	}
+}
 
-	window.app.exit();
+/// Creates the window for showing the picker of `app` on `conn`, along with
+/// the event loop driving it
+fn connect(conn: &Connection, app: App) -> Result<(Window, EventLoop<'static, Window>)> {
+	let event_loop: EventLoop<Window> =
+		EventLoop::try_new().context("Failed to create the event loop")?;
+	let (window, event_queue) = Window::new(conn, event_loop.handle(), app)?;
+	WaylandSource::new(conn.clone(), event_queue)
+		.insert(event_loop.handle())
+		.map_err(|err| anyhow!("Failed to watch the Wayland connection: {}", err.error))?;
+	Ok((window, event_loop))
+}
+
+/// Shows the picker of `window` until it's done, taking commands on `listen`
+/// meanwhile. The window is left open, for launching or serving the clipboard
+/// afterwards.
+fn show(
+	window: &mut Window,
+	event_loop: &mut EventLoop<'static, Window>,
+	conn: &Connection,
+	placement: Placement,
+	listen: Option<&Path>,
+) -> Result<()> {
+	window.open(placement)?;
+	let handle = event_loop.handle();
+	let sources = listen
+		.map(|path| remote::listen(path, &handle, conn.clone(), window.queue_handle().clone()))
+		.transpose()?;
+
+	let mut result = Ok(());
+	while result.is_ok() && window.app.running() {
+		result = event_loop
+			.dispatch(None, window)
+			.context("Lost the connection to Wayland");
+	}
+	debug!("exiting example");
+	sources
+		.into_iter()
+		.flatten()
+		.for_each(|source| handle.remove(source));
+	if let Some(path) = listen {
+		let _ = std::fs::remove_file(path);
+	}
+	result
+}
+
+/// Forks into the background, with the parent exiting right away so whoever
+/// launched us isn't kept waiting while we serve the clipboard
+fn detach(window: &mut Window) {
+	// SAFETY: Only the calling thread carries over to the child, which never
+	// touches the options channel the reader thread may be holding. The parent
+	// exits without touching any shared state.
+	match unsafe { libc::fork() } {
+		-1 => warn!("Failed to fork, serving the clipboard in the foreground"),
+		0 => unsafe {
+			libc::setsid();
+			// Close our end of stdout so pipelines reading from us can finish
+			let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+			if null >= 0 {
+				libc::dup2(null, libc::STDIN_FILENO);
+				libc::dup2(null, libc::STDOUT_FILENO);
+			}
+		},
+		_ => window.app.exit(),
+	}
 }
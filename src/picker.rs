@@ -1,5 +1,9 @@
+use crate::ansi::{self, Style};
+use crate::config::{Case, Sorting, Tiebreak};
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 
 pub struct Search {
 	query: String,
@@ -14,12 +18,34 @@ impl Search {
 		}
 	}
 	pub fn insert(&mut self, ch: char) {
-		let (left, right) = self.query.split_at(self.cursor);
-		let mut new_query = String::from(left);
-		new_query.push(ch);
-		new_query.push_str(right);
-		self.query = new_query;
-		self.cursor += 1;
+		self.query.insert(self.cursor, ch);
+		self.cursor += ch.len_utf8();
+	}
+	/// Inserts pasted or composed text at the cursor, dropping newlines and
+	/// other control characters
+	pub fn insert_str(&mut self, text: &str) {
+		text.chars()
+			.filter(|ch| !ch.is_control())
+			.for_each(|ch| self.insert(ch));
+	}
+	/// Byte offset of the character boundary before the cursor
+	fn prev_boundary(&self) -> usize {
+		self.query[..self.cursor]
+			.char_indices()
+			.next_back()
+			.map_or(0, |(i, _)| i)
+	}
+	/// Byte offset of the character boundary after the cursor
+	fn next_boundary(&self) -> usize {
+		self.query[self.cursor..]
+			.chars()
+			.next()
+			.map_or(self.cursor, |ch| self.cursor + ch.len_utf8())
+	}
+	/// Replaces the query, with the cursor at its end
+	pub fn set_query(&mut self, query: &str) {
+		self.query = query.to_string();
+		self.cursor = self.query.len();
 	}
 	pub fn cursor_to_start(&mut self) {
 		self.cursor = 0;
@@ -32,15 +58,15 @@ impl Search {
 			if self.cursor == 0 {
 				return;
 			}
-			let ch = self.query.remove(self.cursor - 1);
-			self.cursor -= 1;
+			let ch = self.query.remove(self.prev_boundary());
+			self.cursor -= ch.len_utf8();
 			if ch == ' ' {
 				break;
 			}
 		}
-		while let Some(' ') = self.query.chars().nth(self.cursor - 1) {
-			self.query.remove(self.cursor - 1);
+		while self.query[..self.cursor].ends_with(' ') {
 			self.cursor -= 1;
+			self.query.remove(self.cursor);
 		}
 	}
 	pub fn delete_till_start(&mut self) {
@@ -49,74 +75,338 @@ impl Search {
 		self.cursor = 0;
 	}
 	pub fn delete_till_end(&mut self) {
-		let (left, _) = self.query.split_at(self.cursor);
-		self.query = left.to_string();
+		self.query.truncate(self.cursor);
 	}
 	pub fn delete(&mut self) {
 		if self.cursor > 0 {
-			self.cursor -= 1;
-			let (left, right) = self.query.split_at(self.cursor);
-			self.query = format!("{left}{}", &right[1..]);
+			self.cursor = self.prev_boundary();
+			self.query.remove(self.cursor);
+		}
+	}
+	/// Deletes `before` bytes before and `after` bytes after the cursor, as
+	/// requested by input methods. Ranges not ending on a character boundary
+	/// are ignored.
+	pub fn delete_surrounding(&mut self, before: usize, after: usize) {
+		let start = self.cursor.saturating_sub(before);
+		let end = (self.cursor + after).min(self.query.len());
+		if self.query.is_char_boundary(start) && self.query.is_char_boundary(end) {
+			self.query.replace_range(start..end, "");
+			self.cursor = start;
 		}
 	}
 	pub fn cursor_left(&mut self) {
-		self.cursor = self.cursor.saturating_sub(1);
+		self.cursor = self.prev_boundary();
 	}
 	pub fn cursor_right(&mut self) {
-		self.cursor += 1;
-		if self.cursor > self.query.len() {
-			self.cursor = self.query.len();
+		self.cursor = self.next_boundary();
+	}
+}
+
+/// An option as read from the input
+pub struct Choice {
+	/// Matched against and displayed, invalid UTF-8 is replaced
+	text: String,
+	/// The original bytes, only kept when they differ from `text`
+	raw: Option<Box<[u8]>>,
+	/// Where the style of `text` changes, by character index
+	styles: Vec<(usize, Style)>,
+	/// The text followed by keywords, matched against instead of the text
+	/// alone. Only the text is displayed.
+	search: Option<Box<str>>,
+}
+
+impl Choice {
+	pub fn from_bytes(bytes: Vec<u8>) -> Self {
+		match String::from_utf8(bytes) {
+			Ok(text) => text.into(),
+			Err(err) => Self {
+				text: String::from_utf8_lossy(err.as_bytes()).into_owned(),
+				raw: Some(err.into_bytes().into()),
+				styles: Vec::new(),
+				search: None,
+			},
+		}
+	}
+	/// Takes the colors from ANSI escape sequences in `bytes`, leaving them
+	/// out of the text
+	pub fn from_ansi(bytes: Vec<u8>) -> Self {
+		let (stripped, changes) = ansi::parse(&bytes);
+		// Move the changes from byte offsets to characters of the lossy text,
+		// where each invalid sequence is a single replacement character
+		let mut styles: Vec<(usize, Style)> = Vec::with_capacity(changes.len());
+		let mut changes = changes.into_iter().peekable();
+		let (mut offset, mut index) = (0, 0);
+		let lengths = stripped.utf8_chunks().flat_map(|chunk| {
+			let invalid = chunk.invalid().len();
+			chunk
+				.valid()
+				.chars()
+				.map(char::len_utf8)
+				.chain((invalid > 0).then_some(invalid))
+		});
+		for length in lengths {
+			while let Some((_, style)) = changes.next_if(|(change, _)| *change <= offset) {
+				match styles.last_mut() {
+					Some((last, last_style)) if *last == index => *last_style = style,
+					_ => styles.push((index, style)),
+				}
+			}
+			offset += length;
+			index += 1;
 		}
+		let mut choice = Self::from_bytes(stripped);
+		choice.styles = styles;
+		if choice.text.as_bytes() != bytes.as_slice() {
+			choice.raw = Some(bytes.into());
+		}
+		choice
+	}
+	/// Matches `keywords` as well as `text`
+	pub fn with_keywords(text: String, keywords: &[String]) -> Self {
+		let mut choice = Self::from(text);
+		if !keywords.is_empty() {
+			let search = format!("{} {}", choice.text, keywords.join(" "));
+			choice.search = Some(search.into());
+		}
+		choice
+	}
+	pub fn text(&self) -> &str {
+		&self.text
+	}
+	/// What the query is matched against
+	fn search_text(&self) -> &str {
+		self.search.as_deref().unwrap_or(&self.text)
+	}
+	/// The option exactly as it was read, including any escape sequences
+	pub fn bytes(&self) -> &[u8] {
+		self.raw.as_deref().unwrap_or(self.text.as_bytes())
+	}
+	pub fn has_styles(&self) -> bool {
+		!self.styles.is_empty()
+	}
+	/// Style of the character at `index` of the text
+	pub fn style_at(&self, index: usize) -> Style {
+		let end = self.styles.partition_point(|(start, _)| *start <= index);
+		self.styles[..end]
+			.last()
+			.map_or(Style::default(), |(_, style)| *style)
 	}
 }
 
+impl From<String> for Choice {
+	fn from(text: String) -> Self {
+		Self {
+			text,
+			raw: None,
+			styles: Vec::new(),
+			search: None,
+		}
+	}
+}
+
+struct Match {
+	score: i64,
+	/// Index into the options
+	index: usize,
+	/// Positions of the first and last matched characters, only known when
+	/// a tiebreak needs them
+	span: (usize, usize),
+}
+
 pub struct Picker {
 	pub search: Search,
-	matches: Vec<(i64, usize)>,
+	matches: Vec<Match>,
 	selection_index: usize,
-	options: Vec<String>,
+	options: Vec<Choice>,
+	/// Added to the fuzzy score of the options, see `set_boosts`
+	boosts: HashMap<String, i64>,
+	sorting: Sorting,
+	case: Case,
+	/// Match only the first word of the query, see `set_first_word_only`
+	first_word_only: bool,
 }
 
 impl Picker {
-	pub fn new(options: Vec<String>) -> Self {
+	pub fn new(options: Vec<Choice>, sorting: Sorting, case: Case) -> Self {
 		Self {
 			search: Search::new(),
 			matches: Vec::new(),
 			selection_index: 0,
 			options,
+			boosts: HashMap::new(),
+			sorting,
+			case,
+			first_word_only: false,
 		}
 	}
+	/// Ranks the given options higher by their boost whenever they match
+	pub fn set_boosts(&mut self, boosts: HashMap<String, i64>) {
+		self.boosts = boosts;
+	}
+	/// Matches only the first word of the query, leaving the rest for arguments
+	/// of a command
+	pub fn set_first_word_only(&mut self, first_word_only: bool) {
+		self.first_word_only = first_word_only;
+	}
+	/// Removes all options, and with them the matches
+	pub fn clear(&mut self) {
+		self.options.clear();
+		self.matches.clear();
+		self.selection_index = 0;
+	}
+	/// Moves the selection to the match with `text`, returns whether there
+	/// was one
+	pub fn select(&mut self, text: &str) -> bool {
+		match self
+			.matches
+			.iter()
+			.position(|mtch| self.options[mtch.index].text() == text)
+		{
+			Some(index) => {
+				self.selection_index = index;
+				true
+			}
+			None => false,
+		}
+	}
+	/// Adds an option, it is matched against on the next `update`
+	pub fn push(&mut self, option: Choice) {
+		self.options.push(option);
+	}
+	pub fn match_count(&self) -> usize {
+		self.matches.len()
+	}
+	pub fn option_count(&self) -> usize {
+		self.options.len()
+	}
 	pub fn query(&self) -> &str {
 		&self.search.query
 	}
+	/// The part of the query that is matched against
+	fn pattern(&self) -> &str {
+		if self.first_word_only {
+			self.query().split_whitespace().next().unwrap_or_default()
+		} else {
+			self.query()
+		}
+	}
 	pub fn selection_index(&self) -> usize {
 		self.selection_index
 	}
 	pub fn selection(&self) -> Option<&str> {
 		self.matches
 			.get(self.selection_index)
-			.map(|(_, i)| self.options[*i].as_str())
+			.map(|mtch| self.options[mtch.index].text())
+	}
+	/// Index of the selection among the options, in the order they were read
+	pub fn selected_index(&self) -> Option<usize> {
+		self.matches
+			.get(self.selection_index)
+			.map(|mtch| mtch.index)
 	}
+	pub fn selected_choice(&self) -> Option<&Choice> {
+		self.matches
+			.get(self.selection_index)
+			.map(|mtch| &self.options[mtch.index])
+	}
+	/// Position of the cursor in characters from the start of the query
 	pub fn cursor(&self) -> usize {
+		self.search.query[..self.search.cursor].chars().count()
+	}
+	/// Position of the cursor in bytes from the start of the query
+	pub fn cursor_byte_offset(&self) -> usize {
 		self.search.cursor
 	}
+	fn matcher(&self) -> SkimMatcherV2 {
+		let matcher = SkimMatcherV2::default();
+		match self.case {
+			Case::Smart => matcher.smart_case(),
+			Case::Ignore => matcher.ignore_case(),
+			Case::Respect => matcher.respect_case(),
+		}
+	}
 	pub fn update(&mut self) {
 		self.matches.clear();
-		let fuzzy_matcher = SkimMatcherV2::default();
-		for (i, choice) in self.options.iter().enumerate() {
-			if let Some(score) = fuzzy_matcher.fuzzy_match(choice, self.query()) {
-				self.matches.push((score, i));
+		let fuzzy_matcher = self.matcher();
+		let Sorting {
+			sort,
+			tac,
+			tiebreak,
+		} = &self.sorting;
+		let needs_span = tiebreak
+			.iter()
+			.any(|criterion| matches!(criterion, Tiebreak::Begin | Tiebreak::End));
+		let count = self.options.len();
+		for i in 0..count {
+			let index = if *tac { count - 1 - i } else { i };
+			let choice = &self.options[index];
+			let (score, span) = if needs_span {
+				match fuzzy_matcher.fuzzy_indices(choice.search_text(), self.pattern()) {
+					Some((score, indices)) => (
+						score,
+						(
+							indices.first().copied().unwrap_or(0),
+							indices.last().copied().unwrap_or(0),
+						),
+					),
+					None => continue,
+				}
+			} else {
+				match fuzzy_matcher.fuzzy_match(choice.search_text(), self.pattern()) {
+					Some(score) => (score, (0, 0)),
+					None => continue,
+				}
 			};
+			let boost = self.boosts.get(choice.text()).copied().unwrap_or(0);
+			self.matches.push(Match {
+				score: score + boost,
+				index,
+				span,
+			});
+		}
+		if *sort {
+			let options = &self.options;
+			let length = |mtch: &Match| options[mtch.index].text().chars().count();
+			// The matches are already in input order, which the stable sort keeps
+			// for whatever the tiebreak leaves equal
+			self.matches.sort_by(|a, b| {
+				b.score.cmp(&a.score).then_with(|| {
+					tiebreak
+						.iter()
+						.take_while(|criterion| **criterion != Tiebreak::Index)
+						.map(|criterion| match criterion {
+							Tiebreak::Length => length(a).cmp(&length(b)),
+							Tiebreak::Begin => a.span.0.cmp(&b.span.0),
+							// Matches in the keywords end past the text
+							Tiebreak::End => (length(a).saturating_sub(a.span.1))
+								.cmp(&length(b).saturating_sub(b.span.1)),
+							Tiebreak::Index => Ordering::Equal,
+						})
+						.find(|ordering| ordering.is_ne())
+						.unwrap_or(Ordering::Equal)
+				})
+			});
 		}
-		self.matches.sort_by(|a, b| b.0.cmp(&a.0));
 		self.selection_index = self
 			.selection_index
 			.min(self.matches.len().saturating_sub(1));
 	}
-	pub fn get_matches(&self, count: usize) -> impl Iterator<Item = &str> {
+	/// The best `count` matches, each with the index of its first matched
+	/// character
+	pub fn get_matches(&self, count: usize) -> impl Iterator<Item = (&Choice, usize)> {
+		let fuzzy_matcher = self.matcher();
 		self.matches[..count.min(self.matches.len())]
 			.iter()
-			.map(|(_, i)| self.options[*i].as_str())
+			.map(move |mtch| {
+				let choice = &self.options[mtch.index];
+				// Matches in the keywords leave nothing to scroll to
+				let first_match = fuzzy_matcher
+					.fuzzy_indices(choice.search_text(), self.pattern())
+					.and_then(|(_, indices)| indices.first().copied())
+					.filter(|first| *first < choice.text().chars().count())
+					.unwrap_or(0);
+				(choice, first_match)
+			})
 	}
 	pub fn next(&mut self) {
 		self.selection_index += 1;
@@ -128,3 +418,123 @@ impl Picker {
 		self.selection_index = self.selection_index.saturating_sub(1);
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RED: Style = Style {
+		fg: Some([0xcd, 0, 0]),
+		bg: None,
+	};
+
+	#[test]
+	fn ansi_styles_by_character() {
+		let choice = Choice::from_ansi("é\x1b[31mxy\x1b[0mz".as_bytes().to_vec());
+		assert_eq!(choice.text(), "éxyz");
+		assert_eq!(choice.style_at(0), Style::default());
+		assert_eq!(choice.style_at(1), RED);
+		assert_eq!(choice.style_at(2), RED);
+		assert_eq!(choice.style_at(3), Style::default());
+	}
+
+	#[test]
+	fn ansi_invalid_utf8_is_one_character() {
+		// A truncated three byte sequence
+		let choice = Choice::from_ansi(b"\xe2\x82\x1b[31mx".to_vec());
+		assert_eq!(choice.text(), "\u{fffd}x");
+		assert_eq!(choice.style_at(0), Style::default());
+		assert_eq!(choice.style_at(1), RED);
+	}
+
+	#[test]
+	fn ansi_keeps_the_original_bytes() {
+		let bytes = b"\x1b[31mx\x1b[0m".to_vec();
+		let choice = Choice::from_ansi(bytes.clone());
+		assert_eq!(choice.text(), "x");
+		assert_eq!(choice.bytes(), bytes);
+	}
+
+	#[test]
+	fn no_styles_without_sequences() {
+		let choice = Choice::from_ansi(b"plain".to_vec());
+		assert!(!choice.has_styles());
+		assert_eq!(choice.bytes(), b"plain");
+	}
+
+	/// The options matching `query` in the order they're shown
+	fn order(
+		options: &[&str],
+		query: &str,
+		sort: bool,
+		tac: bool,
+		tiebreak: &[Tiebreak],
+	) -> Vec<String> {
+		let options = options
+			.iter()
+			.map(|option| option.to_string().into())
+			.collect();
+		let sorting = Sorting {
+			sort,
+			tac,
+			tiebreak: tiebreak.to_vec(),
+		};
+		let mut picker = Picker::new(options, sorting, Case::Smart);
+		picker.search.set_query(query);
+		picker.update();
+		picker
+			.get_matches(usize::MAX)
+			.map(|(choice, _)| choice.text().to_string())
+			.collect()
+	}
+
+	#[test]
+	fn higher_scores_first() {
+		let order = order(&["a_b", "ab"], "ab", true, false, &[Tiebreak::Index]);
+		assert_eq!(order, ["ab", "a_b"]);
+	}
+
+	#[test]
+	fn tiebreak_length() {
+		let order = order(&["abc", "a", "ab"], "", true, false, &[Tiebreak::Length]);
+		assert_eq!(order, ["a", "ab", "abc"]);
+	}
+
+	#[test]
+	fn tiebreak_begin() {
+		let order = order(&["abxb", "axbb"], "x", true, false, &[Tiebreak::Begin]);
+		assert_eq!(order, ["axbb", "abxb"]);
+	}
+
+	#[test]
+	fn tiebreak_end() {
+		let order = order(&["axbb", "abxb"], "x", true, false, &[Tiebreak::End]);
+		assert_eq!(order, ["abxb", "axbb"]);
+	}
+
+	#[test]
+	fn tiebreak_index_ends_the_tiebreak() {
+		let tiebreak = [Tiebreak::Index, Tiebreak::Length];
+		let order = order(&["abc", "a", "ab"], "", true, false, &tiebreak);
+		assert_eq!(order, ["abc", "a", "ab"]);
+	}
+
+	#[test]
+	fn tiebreak_falls_back_to_input_order() {
+		let order = order(&["ba", "ca", "da"], "a", true, false, &[Tiebreak::Length]);
+		assert_eq!(order, ["ba", "ca", "da"]);
+	}
+
+	#[test]
+	fn tac_reverses_equal_scores() {
+		let order = order(&["ba", "ca", "da"], "a", true, true, &[Tiebreak::Length]);
+		assert_eq!(order, ["da", "ca", "ba"]);
+	}
+
+	#[test]
+	fn no_sort_keeps_the_input_order() {
+		let options = ["a_b", "xyz", "ab"];
+		assert_eq!(order(&options, "ab", false, false, &[]), ["a_b", "ab"]);
+		assert_eq!(order(&options, "ab", false, true, &[]), ["ab", "a_b"]);
+	}
+}
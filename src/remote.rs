@@ -0,0 +1,177 @@
+use crate::bindings::Action;
+use crate::events::Event;
+use crate::window::Window;
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use smithay_client_toolkit::reexports::calloop::{
+	channel, generic::Generic, Interest, LoopHandle, Mode, PostAction, RegistrationToken,
+};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use wayland_client::{Connection, QueueHandle};
+
+/// A line received on the socket, along with where its reply goes
+struct Message {
+	line: String,
+	reply: mpsc::Sender<String>,
+}
+
+/// Listens on a Unix socket at `path` for commands controlling the picker.
+///
+/// Each line sent is a command, answered with a single line:
+/// - `state` is answered with the state of the picker as JSON
+/// - Anything else is an action in the syntax of `--bind`, like `up`,
+///   `accept` or `change-query(text)`, answered with `ok` or an error
+///
+/// Connections are accepted by the event loop, so the socket closes once the
+/// returned sources are removed from it, or along with it. They're served on
+/// threads of their own, with the commands handed back to the event loop.
+pub fn listen(
+	path: &Path,
+	handle: &LoopHandle<'static, Window>,
+	conn: Connection,
+	qh: QueueHandle<Window>,
+) -> Result<[RegistrationToken; 2]> {
+	let listener = bind(path)?;
+	listener
+		.set_nonblocking(true)
+		.with_context(|| format!("Failed to listen on {}", path.display()))?;
+	let (sender, receiver) = channel::channel::<Message>();
+	let commands = handle
+		.insert_source(receiver, move |event, _, window| {
+			if let channel::Event::Msg(message) = event {
+				let reply = handle_command(window, &conn, &qh, &message.line);
+				// The client may be gone already, which is fine
+				let _ = message.reply.send(reply);
+			}
+		})
+		.map_err(|err| anyhow!("Failed to watch the socket: {}", err.error))?;
+	let listener = Generic::new(listener, Interest::READ, Mode::Level);
+	let connections = handle
+		.insert_source(listener, move |_, listener, _| {
+			loop {
+				match listener.accept() {
+					Ok((stream, _)) => {
+						let sender = sender.clone();
+						thread::spawn(move || serve(stream, sender));
+					}
+					Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+					Err(err) => {
+						warn!("Failed to accept a connection: {err}");
+						break;
+					}
+				}
+			}
+			Ok(PostAction::Continue)
+		})
+		.map_err(|err| {
+			handle.remove(commands);
+			anyhow!("Failed to watch the socket: {}", err.error)
+		})?;
+	Ok([commands, connections])
+}
+
+/// Binds a Unix socket at `path`, replacing one left behind by a process
+/// that's gone
+pub fn bind(path: &Path) -> Result<UnixListener> {
+	match std::fs::symlink_metadata(path) {
+		Ok(metadata) if !metadata.file_type().is_socket() => {
+			return Err(anyhow!("{} exists and isn't a socket", path.display()));
+		}
+		Ok(_) => match UnixStream::connect(path) {
+			Ok(_) => return Err(anyhow!("{} is already in use", path.display())),
+			Err(_) => std::fs::remove_file(path)
+				.with_context(|| format!("Failed to remove {}", path.display()))?,
+		},
+		Err(err) if err.kind() == ErrorKind::NotFound => {}
+		Err(err) => {
+			return Err(err).with_context(|| format!("Failed to inspect {}", path.display()))
+		}
+	}
+	UnixListener::bind(path).with_context(|| format!("Failed to listen on {}", path.display()))
+}
+
+/// Relays the commands of a single connection
+fn serve(stream: UnixStream, sender: channel::Sender<Message>) {
+	// Only the listening socket is non-blocking
+	let writer = stream
+		.set_nonblocking(false)
+		.and_then(|()| stream.try_clone());
+	let mut writer = match writer {
+		Ok(writer) => writer,
+		Err(err) => {
+			warn!("Failed to serve a connection: {err}");
+			return;
+		}
+	};
+	for line in BufReader::new(stream).lines() {
+		let Ok(line) = line else {
+			return;
+		};
+		let (reply, replies) = mpsc::channel();
+		if sender.send(Message { line, reply }).is_err() {
+			return;
+		}
+		// No reply means the picker is done
+		let Ok(reply) = replies.recv() else {
+			return;
+		};
+		if let Err(err) = writeln!(writer, "{reply}") {
+			if err.kind() != ErrorKind::BrokenPipe {
+				warn!("Failed to reply: {err}");
+			}
+			return;
+		}
+	}
+}
+
+fn handle_command(
+	window: &mut Window,
+	conn: &Connection,
+	qh: &QueueHandle<Window>,
+	command: &str,
+) -> String {
+	match command.trim() {
+		"state" => window.app.state().to_string(),
+		action => match action.parse::<Action>() {
+			Ok(action) => {
+				window.handle_event(conn, qh, Event::Action(action));
+				"ok".to_string()
+			}
+			Err(err) => format!("error: {err}"),
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn temporary(name: &str) -> std::path::PathBuf {
+		std::env::temp_dir().join(format!("fzf_gui-{}-{name}", std::process::id()))
+	}
+
+	#[test]
+	fn bind_keeps_other_files() {
+		let path = temporary("notes.txt");
+		std::fs::write(&path, "notes").unwrap();
+		assert!(bind(&path).is_err());
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "notes");
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn bind_replaces_stale_sockets() {
+		let path = temporary("stale.sock");
+		drop(UnixListener::bind(&path).unwrap());
+		let listener = bind(&path).unwrap();
+		// Now that someone is listening, it's in use
+		assert!(bind(&path).is_err());
+		drop(listener);
+		std::fs::remove_file(&path).unwrap();
+	}
+}
@@ -1,68 +1,252 @@
-use crate::{app::App, events::Event, Mode, THEME};
-use log::{log_enabled, Level};
+mod scale;
+mod text_input;
+
+use crate::{
+	app::{App, ABORTED},
+	config::{Length, Placement, Position},
+	events::{Clipboard, Event, Request},
+	FONT_SIZE, WINDOW_SIZE,
+};
+use anyhow::{anyhow, Context, Result};
+use log::{log_enabled, warn, Level};
 use smithay_client_toolkit::{
-	compositor::{CompositorHandler, CompositorState},
-	delegate_compositor, delegate_keyboard, delegate_layer, delegate_output, delegate_pointer,
+	activation::{ActivationHandler, ActivationState, RequestData},
+	compositor::{CompositorHandler, CompositorState, SurfaceData},
+	data_device_manager::{
+		data_device::{DataDevice, DataDeviceHandler},
+		data_offer::{DataOfferHandler, DragOffer},
+		data_source::{CopyPasteSource, DataSourceHandler},
+		DataDeviceManagerState, WritePipe,
+	},
+	delegate_activation, delegate_compositor, delegate_data_device, delegate_keyboard,
+	delegate_layer, delegate_output, delegate_pointer, delegate_primary_selection,
 	delegate_registry, delegate_seat, delegate_shm,
+	globals::GlobalData,
 	output::{OutputHandler, OutputState},
+	primary_selection::{
+		device::{PrimarySelectionDevice, PrimarySelectionDeviceHandler},
+		selection::PrimarySelectionSourceHandler,
+		PrimarySelectionManagerState,
+	},
+	reexports::{
+		calloop::{LoopHandle, PostAction},
+		protocols::wp::primary_selection::zv1::client::{
+			zwp_primary_selection_device_v1::ZwpPrimarySelectionDeviceV1,
+			zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+		},
+	},
 	registry::{ProvidesRegistryState, RegistryState},
 	registry_handlers,
 	seat::{
 		keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers},
-		pointer::{PointerEvent, PointerHandler},
+		pointer::{PointerEvent, PointerEventKind, PointerHandler, BTN_MIDDLE},
 		Capability, SeatHandler, SeatState,
 	},
 	shell::{
 		wlr_layer::{
-			KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
+			Anchor, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
 			LayerSurfaceConfigure,
 		},
 		WaylandSurface,
 	},
 	shm::{slot::SlotPool, Shm, ShmHandler},
 };
+use std::io::{ErrorKind, Read, Write};
 use wayland_client::{
 	globals::registry_queue_init,
-	protocol::{wl_keyboard, wl_output, wl_pointer, wl_seat, wl_shm, wl_surface},
-	Connection, EventQueue, QueueHandle,
+	protocol::{
+		wl_data_device::WlDataDevice, wl_data_device_manager::DndAction,
+		wl_data_source::WlDataSource, wl_keyboard, wl_output, wl_pointer, wl_seat, wl_shm,
+		wl_surface,
+	},
+	Connection, EventQueue, Proxy, QueueHandle,
 };
+use wayland_protocols::wp::text_input::zv3::client::zwp_text_input_manager_v3::ZwpTextInputManagerV3;
+
+use scale::FractionalScale;
+use text_input::TextInput;
+use wayland_protocols::wp::{
+	fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+	viewporter::client::wp_viewporter::WpViewporter,
+};
+
+/// Upper limit on the number of bytes read from a single paste
+const MAX_PASTE_SIZE: usize = 4096;
+
+/// Mime types accepted for pasting, in order of preference
+const TEXT_MIME_TYPES: [&str; 5] = [
+	"text/plain;charset=utf-8",
+	"UTF8_STRING",
+	"text/plain",
+	"STRING",
+	"TEXT",
+];
 
 /// Manages interfacing with Wayland
 pub struct Window {
 	registry_state: RegistryState,
 	seat_state: SeatState,
 	output_state: OutputState,
+	compositor: CompositorState,
+	layer_shell: LayerShell,
 	shm: Shm,
 	first_configure: bool,
 	pool: SlotPool,
+	/// Logical size of the surface
 	width: u32,
 	height: u32,
-	layer: LayerSurface,
+	placement: Placement,
+	/// The output the requested size was last computed for
+	sized_for: Option<wl_output::WlOutput>,
+	/// Integer scale of the outputs the surface is on
+	scale_factor: i32,
+	fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+	viewporter: Option<WpViewporter>,
+	fractional_scale: Option<FractionalScale>,
+	/// The surface of the window while it's open
+	layer: Option<LayerSurface>,
+	/// The seat of the keyboard
+	seat: Option<wl_seat::WlSeat>,
 	keyboard: Option<wl_keyboard::WlKeyboard>,
 	modifiers: Modifiers,
 	keyboard_focus: bool,
 	pointer: Option<wl_pointer::WlPointer>,
+	data_device_manager: Option<DataDeviceManagerState>,
+	data_device: Option<DataDevice>,
+	/// The clipboard we currently own along with its contents
+	clipboard: Option<(CopyPasteSource, String)>,
+	/// Serial of the latest key press, needed to take over the clipboard
+	last_serial: u32,
+	primary_selection_manager: Option<PrimarySelectionManagerState>,
+	primary_selection_device: Option<PrimarySelectionDevice>,
+	text_input_manager: Option<ZwpTextInputManagerV3>,
+	text_input: Option<TextInput>,
+	activation: Option<ActivationState>,
+	/// Whether a requested activation token is yet to arrive
+	activation_pending: bool,
+	activation_token: Option<String>,
+	/// For reading pasted content without blocking
+	loop_handle: LoopHandle<'static, Window>,
+	qh: QueueHandle<Self>,
 	// Can't be a generic since delegate_* macros require 'static lifetime
 	pub app: App,
 }
 
 impl Window {
-	pub fn new(width: u32, height: u32, app: App) -> (Self, EventQueue<Self>) {
-		let conn = Connection::connect_to_env().unwrap();
-		let (globals, event_queue) = registry_queue_init(&conn).unwrap();
+	/// Binds the globals of the compositor, ready to `open` the window for
+	/// `app`. The same window can be opened again after closing it, so the
+	/// globals are only bound once.
+	pub fn new(
+		conn: &Connection,
+		loop_handle: LoopHandle<'static, Window>,
+		app: App,
+	) -> Result<(Self, EventQueue<Self>)> {
+		let (globals, mut event_queue) =
+			registry_queue_init(conn).context("Failed to list the Wayland globals")?;
 		let qh: QueueHandle<Self> = event_queue.handle();
 
 		let compositor =
-			CompositorState::bind(&globals, &qh).expect("wl_compositor is not available");
+			CompositorState::bind(&globals, &qh).context("wl_compositor is not available")?;
 
-		let layer_shell = LayerShell::bind(&globals, &qh).expect("layer shell is not available");
+		let layer_shell = LayerShell::bind(&globals, &qh)
+			.context("The compositor doesn't support the layer shell")?;
 
-		let shm = Shm::bind(&globals, &qh).expect("wl_shm is not available");
+		let shm = Shm::bind(&globals, &qh).context("wl_shm is not available")?;
 
-		let surface = compositor.create_surface(&qh);
+		// Pasting is optional, so these are allowed to be missing
+		let data_device_manager = DataDeviceManagerState::bind(&globals, &qh).ok();
+		let primary_selection_manager = PrimarySelectionManagerState::bind(&globals, &qh).ok();
+		// Same goes for input methods
+		let text_input_manager = globals.bind(&qh, 1..=1, GlobalData).ok();
+		// And for giving launched applications the focus
+		let activation = ActivationState::bind(&globals, &qh).ok();
 
-		let layer = layer_shell.create_layer_surface(
-			&qh,
+		let placement = Placement::default();
+		let (width, height) = window_size(&placement, None);
+		let pool = SlotPool::new((width * height * 4) as usize, &shm)
+			.context("Failed to create memory pool")?;
+
+		let mut window = Self {
+			registry_state: RegistryState::new(&globals),
+			seat_state: SeatState::new(&globals, &qh),
+			output_state: OutputState::new(&globals, &qh),
+			compositor,
+			layer_shell,
+			shm,
+			first_configure: true,
+			pool,
+			width,
+			height,
+			placement,
+			sized_for: None,
+			scale_factor: 1,
+			fractional_scale_manager: globals.bind(&qh, 1..=1, GlobalData).ok(),
+			viewporter: globals.bind(&qh, 1..=1, GlobalData).ok(),
+			fractional_scale: None,
+			layer: None,
+			seat: None,
+			keyboard: None,
+			keyboard_focus: false,
+			// TODO: Handle the case when modifiers are already activated
+			modifiers: Modifiers::default(),
+			pointer: None,
+			data_device_manager,
+			data_device: None,
+			clipboard: None,
+			last_serial: 0,
+			primary_selection_manager,
+			primary_selection_device: None,
+			text_input_manager,
+			text_input: None,
+			activation,
+			activation_pending: false,
+			activation_token: None,
+			loop_handle,
+			qh,
+			app,
+		};
+
+		// Output names only arrive after binding the outputs, and the xdg-output
+		// information after that
+		event_queue
+			.roundtrip(&mut window)
+			.and_then(|_| event_queue.roundtrip(&mut window))
+			.context("Failed to list the outputs")?;
+
+		Ok((window, event_queue))
+	}
+
+	/// Shows the window for `app` as placed by `placement`
+	pub fn open(&mut self, placement: Placement) -> Result<()> {
+		let output = placement
+			.output
+			.as_deref()
+			.map(|name| self.find_output(name))
+			.transpose()?;
+		self.placement = placement;
+		// Without a named output, relative sizes can only be known up front if
+		// there's just the one
+		let sizing_output = output.clone().or_else(|| {
+			let mut outputs = self.output_state.outputs();
+			match (outputs.next(), outputs.next()) {
+				(Some(output), None) => Some(output),
+				_ => None,
+			}
+		});
+		let (width, height) = self.size_for(sizing_output);
+		(self.width, self.height) = (width, height);
+		self.first_configure = true;
+		self.scale_factor = 1;
+
+		let surface = self.compositor.create_surface(&self.qh);
+		self.fractional_scale = FractionalScale::new(
+			self.fractional_scale_manager.as_ref(),
+			self.viewporter.as_ref(),
+			&surface,
+			&self.qh,
+		);
+		let layer = self.layer_shell.create_layer_surface(
+			&self.qh,
 			surface,
 			if log_enabled!(Level::Debug) {
 				Layer::Top
@@ -70,7 +254,7 @@ impl Window {
 				Layer::Overlay
 			},
 			Some("fzf"),
-			None,
+			output.as_ref(),
 		);
 		layer.set_keyboard_interactivity(if log_enabled!(Level::Debug) {
 			KeyboardInteractivity::OnDemand
@@ -78,42 +262,114 @@ impl Window {
 			KeyboardInteractivity::Exclusive
 		});
 		layer.set_size(width, height);
+		let [top, right, bottom, left] = self.placement.margin;
+		layer.set_anchor(match self.placement.position {
+			Position::Top => Anchor::TOP,
+			// Not anchoring at all leaves the window centered
+			Position::Center => Anchor::empty(),
+			Position::Bottom => Anchor::BOTTOM,
+		});
+		layer.set_margin(top, right, bottom, left);
 		layer.commit();
+		self.layer = Some(layer);
+		Ok(())
+	}
 
-		let pool = SlotPool::new((width * height * 4) as usize, &shm)
-			.expect("Failed to create memory pool");
-
-		(
-			Self {
-				registry_state: RegistryState::new(&globals),
-				seat_state: SeatState::new(&globals, &qh),
-				output_state: OutputState::new(&globals, &qh),
-				shm,
-				first_configure: true,
-				pool,
-				width,
-				height,
-				layer,
-				keyboard: None,
-				keyboard_focus: false,
-				// TODO: Handle the case when modifiers are already activated
-				modifiers: Modifiers::default(),
-				pointer: None,
-				app,
-			},
-			event_queue,
-		)
+	/// The surface of the window while it's open
+	fn surface(&self) -> Option<&wl_surface::WlSurface> {
+		self.layer.as_ref().map(LayerSurface::wl_surface)
+	}
+
+	/// For handing events to `handle_event` from outside of the event queue
+	pub fn queue_handle(&self) -> &QueueHandle<Self> {
+		&self.qh
+	}
+
+	/// Computes the requested size for `output` and remembers it was done so
+	fn size_for(&mut self, output: Option<wl_output::WlOutput>) -> (u32, u32) {
+		let output_size = output
+			.as_ref()
+			.and_then(|output| self.output_state.info(output))
+			.and_then(|info| info.logical_size);
+		self.sized_for = output;
+		window_size(&self.placement, output_size)
+	}
+
+	/// Sizes relative to the output get recomputed once the surface shows up
+	/// on one, so the window follows whichever output the compositor picked
+	fn follow_output(&mut self) {
+		let relative = [self.placement.width, self.placement.height]
+			.iter()
+			.any(|length| matches!(length, Length::Percent(_)));
+		let output = self
+			.surface()
+			.and_then(|surface| surface.data::<SurfaceData>())
+			.and_then(|data| data.outputs().next());
+		if !relative || output.is_none() || output == self.sized_for {
+			return;
+		}
+		let (width, height) = self.size_for(output);
+		// Takes effect with the next commit, followed by a configure
+		if let Some(layer) = &self.layer {
+			layer.set_size(width, height);
+		}
+	}
+
+	/// Looks up an output by its name, such as `DP-1`
+	fn find_output(&self, name: &str) -> Result<wl_output::WlOutput> {
+		let output_name = |output: &wl_output::WlOutput| {
+			self.output_state.info(output).and_then(|info| info.name)
+		};
+		if let Some(output) = self
+			.output_state
+			.outputs()
+			.find(|output| output_name(output).as_deref() == Some(name))
+		{
+			return Ok(output);
+		}
+		let available: Vec<_> = self
+			.output_state
+			.outputs()
+			.filter_map(|i| output_name(&i))
+			.collect();
+		Err(anyhow!(
+			"No output named {name}, available outputs: {}",
+			available.join(", ")
+		))
 	}
 }
+/// Logical size of the window for `placement` on an output of `output_size`,
+/// sizes relative to the output fall back to the defaults while it is unknown
+fn window_size(placement: &Placement, output_size: Option<(i32, i32)>) -> (u32, u32) {
+	let glyph_width = (FONT_SIZE / 2) as u32;
+	let glyph_height = FONT_SIZE as u32;
+	let width = match (placement.width, output_size) {
+		(Length::Percent(percent), Some((width, _))) => width as u32 * percent / 100,
+		(Length::Percent(_), None) => WINDOW_SIZE.0 as u32 * glyph_width,
+		(Length::Glyphs(glyphs), _) => glyphs * glyph_width,
+	};
+	let height = match (placement.height, output_size) {
+		(Length::Percent(percent), Some((_, height))) => height as u32 * percent / 100,
+		// +2 for rendering the top and bottom borders (1px each)
+		(Length::Percent(_), None) => WINDOW_SIZE.1 as u32 * glyph_height + 2,
+		(Length::Glyphs(glyphs), _) => glyphs * glyph_height + 2,
+	};
+	(width, height)
+}
+
 impl CompositorHandler for Window {
 	fn scale_factor_changed(
 		&mut self,
 		_conn: &Connection,
 		_qh: &QueueHandle<Self>,
 		_surface: &wl_surface::WlSurface,
-		_new_factor: i32,
+		new_factor: i32,
 	) {
-		// Not needed for this example.
+		self.scale_factor = new_factor;
+		// The fractional scale takes precedence when it is available
+		if self.fractional_scale.is_none() {
+			self.scale_changed();
+		}
 	}
 
 	fn transform_changed(
@@ -169,7 +425,8 @@ impl OutputHandler for Window {
 
 impl LayerShellHandler for Window {
 	fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
-		self.app.close(0);
+		// Like when the output goes away, nothing was accepted
+		self.app.close(ABORTED);
 	}
 
 	fn configure(
@@ -180,10 +437,7 @@ impl LayerShellHandler for Window {
 		configure: LayerSurfaceConfigure,
 		_serial: u32,
 	) {
-		if configure.new_size.0 == 0 || configure.new_size.1 == 0 {
-			self.width = self.width;
-			self.height = self.height;
-		} else {
+		if configure.new_size.0 != 0 && configure.new_size.1 != 0 {
 			self.width = configure.new_size.0;
 			self.height = configure.new_size.1;
 		}
@@ -216,6 +470,19 @@ impl SeatHandler for Window {
 				.get_keyboard(qh, &seat, None)
 				.expect("Failed to create keyboard");
 			self.keyboard = Some(keyboard);
+			self.seat = Some(seat.clone());
+			self.data_device = self
+				.data_device_manager
+				.as_ref()
+				.map(|manager| manager.get_data_device(qh, &seat));
+			self.primary_selection_device = self
+				.primary_selection_manager
+				.as_ref()
+				.map(|manager| manager.get_selection_device(qh, &seat));
+			self.text_input = self
+				.text_input_manager
+				.as_ref()
+				.map(|manager| TextInput::new(manager.get_text_input(&seat, qh, ())));
 		}
 
 		if capability == Capability::Pointer && self.pointer.is_none() {
@@ -249,47 +516,52 @@ impl SeatHandler for Window {
 impl KeyboardHandler for Window {
 	fn enter(
 		&mut self,
-		_: &Connection,
-		_: &QueueHandle<Self>,
+		conn: &Connection,
+		qh: &QueueHandle<Self>,
 		_: &wl_keyboard::WlKeyboard,
 		surface: &wl_surface::WlSurface,
 		_: u32,
 		_: &[u32],
 		_: &[Keysym],
 	) {
-		if self.layer.wl_surface() == surface {
+		if self.surface() == Some(surface) {
 			self.keyboard_focus = true;
-			self.app.handle_events(Event::Focused(true));
+			self.handle_event(conn, qh, Event::Focused(true));
 		}
 	}
 
 	fn leave(
 		&mut self,
-		_: &Connection,
-		_: &QueueHandle<Self>,
+		conn: &Connection,
+		qh: &QueueHandle<Self>,
 		_: &wl_keyboard::WlKeyboard,
 		surface: &wl_surface::WlSurface,
 		_: u32,
 	) {
-		if self.layer.wl_surface() == surface {
+		if self.surface() == Some(surface) {
 			self.keyboard_focus = false;
-			self.app.handle_events(Event::Focused(false));
+			self.handle_event(conn, qh, Event::Focused(false));
 		}
 	}
 
 	fn press_key(
 		&mut self,
-		_conn: &Connection,
-		_qh: &QueueHandle<Self>,
+		conn: &Connection,
+		qh: &QueueHandle<Self>,
 		_: &wl_keyboard::WlKeyboard,
-		_: u32,
+		serial: u32,
 		event: KeyEvent,
 	) {
-		self.app.handle_events(Event::Keyboard {
-			modifiers: self.modifiers,
-			keycode: event.keysym,
-			utf8: event.utf8,
-		});
+		self.last_serial = serial;
+		self.handle_event(
+			conn,
+			qh,
+			Event::Keyboard {
+				modifiers: self.modifiers,
+				keycode: event.keysym,
+				utf8: event.utf8,
+			},
+		);
 	}
 
 	fn release_key(
@@ -317,12 +589,141 @@ impl KeyboardHandler for Window {
 impl PointerHandler for Window {
 	fn pointer_frame(
 		&mut self,
-		_conn: &Connection,
-		_qh: &QueueHandle<Self>,
+		conn: &Connection,
+		qh: &QueueHandle<Self>,
 		_pointer: &wl_pointer::WlPointer,
-		_events: &[PointerEvent],
+		events: &[PointerEvent],
+	) {
+		for event in events {
+			if self.surface() != Some(&event.surface) {
+				continue;
+			}
+			if let PointerEventKind::Press {
+				button: BTN_MIDDLE, ..
+			} = event.kind
+			{
+				self.handle_event(conn, qh, Event::MiddleClick);
+			}
+		}
+	}
+}
+
+impl DataDeviceHandler for Window {
+	fn enter(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+	fn leave(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+	fn motion(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+	fn selection(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {
+		// The offer is looked up on demand when pasting
+	}
+
+	fn drop_performed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+}
+
+impl DataOfferHandler for Window {
+	fn source_actions(
+		&mut self,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+		_: &mut DragOffer,
+		_: DndAction,
+	) {
+	}
+
+	fn selected_action(
+		&mut self,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+		_: &mut DragOffer,
+		_: DndAction,
 	) {
-		// Do nothing with mouse events
+	}
+}
+
+impl DataSourceHandler for Window {
+	fn accept_mime(
+		&mut self,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+		_: &WlDataSource,
+		_: Option<String>,
+	) {
+	}
+
+	fn send_request(
+		&mut self,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+		source: &WlDataSource,
+		_: String,
+		mut fd: WritePipe,
+	) {
+		if let Some((_, text)) = self
+			.clipboard
+			.as_ref()
+			.filter(|(ours, _)| ours.inner() == source)
+		{
+			if let Err(err) = fd.write_all(text.as_bytes()) {
+				warn!("Failed to send clipboard content: {err}");
+			}
+		}
+	}
+
+	fn cancelled(&mut self, _: &Connection, _: &QueueHandle<Self>, source: &WlDataSource) {
+		// Some other client took over the clipboard
+		if let Some((ours, _)) = &self.clipboard {
+			if ours.inner() == source {
+				self.clipboard = None;
+			}
+		}
+	}
+
+	fn dnd_dropped(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+	fn dnd_finished(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+	fn action(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource, _: DndAction) {}
+}
+
+impl PrimarySelectionDeviceHandler for Window {
+	fn selection(
+		&mut self,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+		_: &ZwpPrimarySelectionDeviceV1,
+	) {
+		// The offer is looked up on demand when pasting
+	}
+}
+
+impl PrimarySelectionSourceHandler for Window {
+	fn send_request(
+		&mut self,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+		_: &ZwpPrimarySelectionSourceV1,
+		_: String,
+		_: WritePipe,
+	) {
+	}
+
+	fn cancelled(
+		&mut self,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+		_: &ZwpPrimarySelectionSourceV1,
+	) {
+	}
+}
+
+impl ActivationHandler for Window {
+	type RequestData = RequestData;
+
+	fn new_token(&mut self, token: String, _data: &Self::RequestData) {
+		self.activation_token = Some(token);
+		self.activation_pending = false;
 	}
 }
 
@@ -333,15 +734,171 @@ impl ShmHandler for Window {
 }
 
 impl Window {
-	pub fn draw(&mut self, qh: &QueueHandle<Self>) {
-		let width = self.width;
-		let height = self.height;
-		let stride = self.width as i32 * 4;
-		let border_color = match THEME {
-			Mode::Dark => 0xff,
-			Mode::Light => 0x00,
+	/// Forwards `event` to the `App` and carries out whatever it requests in response
+	pub fn handle_event(&mut self, conn: &Connection, qh: &QueueHandle<Self>, event: Event) {
+		self.app.handle_events(event);
+		for request in self.app.take_requests() {
+			match request {
+				Request::Paste(clipboard) => self.paste(conn, qh, clipboard),
+				Request::Copy(text) => self.copy(qh, text),
+				Request::ActivationToken => self.request_activation_token(qh),
+			}
+		}
+	}
+
+	/// Takes over the regular clipboard and offers `text` on it
+	fn copy(&mut self, qh: &QueueHandle<Self>, text: String) {
+		let (Some(manager), Some(device)) = (&self.data_device_manager, &self.data_device) else {
+			warn!("Copying to the clipboard is not supported by the compositor");
+			return;
+		};
+		let source = manager.create_copy_paste_source(qh, TEXT_MIME_TYPES);
+		source.set_selection(device, self.last_serial);
+		self.clipboard = Some((source, text));
+	}
+
+	/// Asks for a token for the application about to be launched, tied to the
+	/// latest key press so the compositor lets the application take the focus
+	fn request_activation_token(&mut self, qh: &QueueHandle<Self>) {
+		let Some(activation) = &self.activation else {
+			return;
+		};
+		activation.request_token(
+			qh,
+			RequestData {
+				app_id: None,
+				seat_and_serial: self.seat.clone().map(|seat| (seat, self.last_serial)),
+				surface: self.surface().cloned(),
+			},
+		);
+		self.activation_pending = true;
+	}
+
+	/// Whether a requested activation token is yet to arrive
+	pub fn activation_pending(&self) -> bool {
+		self.activation_pending
+	}
+
+	pub fn take_activation_token(&mut self) -> Option<String> {
+		self.activation_token.take()
+	}
+
+	/// Whether we still have to serve the clipboard to other clients
+	pub fn owns_clipboard(&self) -> bool {
+		self.clipboard.is_some()
+	}
+
+	/// Unmaps the surface, leaving the connection usable for the clipboard
+	pub fn hide(&mut self) {
+		if let Some(surface) = self.surface() {
+			surface.attach(None, 0, 0);
+			surface.commit();
+		}
+	}
+	/// Destroys the surface of the window along with everything tied to it,
+	/// keeping the globals and the seat around for opening it again
+	pub fn close(&mut self) {
+		// The extensions of the surface go before the surface itself
+		self.fractional_scale = None;
+		self.layer = None;
+		self.sized_for = None;
+		self.keyboard_focus = false;
+		self.activation_pending = false;
+		self.activation_token = None;
+	}
+
+	/// Reads the text content of `clipboard` and hands it to the `App`
+	fn paste(&mut self, conn: &Connection, qh: &QueueHandle<Self>, clipboard: Clipboard) {
+		let text_mime_type = |mime_types: &[String]| {
+			TEXT_MIME_TYPES
+				.iter()
+				.find(|mime| mime_types.iter().any(|i| i == *mime))
+				.map(|mime| mime.to_string())
+		};
+		let pipe = match clipboard {
+			Clipboard::Regular => self
+				.data_device
+				.as_ref()
+				.and_then(|device| device.data().selection_offer())
+				.and_then(|offer| {
+					let mime_type = offer.with_mime_types(text_mime_type)?;
+					offer.receive(mime_type).ok()
+				}),
+			Clipboard::Primary => self
+				.primary_selection_device
+				.as_ref()
+				.and_then(|device| device.data().selection_offer())
+				.and_then(|offer| {
+					let mime_type = offer.with_mime_types(text_mime_type)?;
+					offer.receive(mime_type).ok()
+				}),
+		};
+		let Some(pipe) = pipe else {
+			return;
 		};
+		// The source client only starts writing once our receive request reaches it
+		if let Err(err) = conn.flush() {
+			warn!("Failed to request {clipboard:?} clipboard content: {err}");
+			return;
+		}
+		// The source client may take its time or never finish writing, so the
+		// content is read as it arrives instead of blocking on it
+		let conn = conn.clone();
+		let qh = qh.clone();
+		let mut bytes = Vec::new();
+		let inserted = self
+			.loop_handle
+			.insert_source(pipe, move |(), file, window| {
+				let mut chunk = [0; 1024];
+				let limit = chunk.len().min(MAX_PASTE_SIZE - bytes.len());
+				// SAFETY: The file is only read from, never replaced or dropped
+				match unsafe { file.get_mut() }.read(&mut chunk[..limit]) {
+					Ok(0) => (),
+					Ok(read) => {
+						bytes.extend_from_slice(&chunk[..read]);
+						if bytes.len() < MAX_PASTE_SIZE {
+							return PostAction::Continue;
+						}
+						bytes.truncate(complete_utf8(&bytes));
+					}
+					Err(err) if err.kind() == ErrorKind::Interrupted => {
+						return PostAction::Continue
+					}
+					Err(err) => {
+						warn!("Failed to read {clipboard:?} clipboard content: {err}");
+						return PostAction::Remove;
+					}
+				}
+				let text = String::from_utf8_lossy(&bytes).into_owned();
+				window.handle_event(&conn, &qh, Event::Paste(text));
+				PostAction::Remove
+			});
+		if let Err(err) = inserted {
+			warn!(
+				"Failed to read {clipboard:?} clipboard content: {}",
+				err.error
+			);
+		}
+	}
 
+	pub fn draw(&mut self, qh: &QueueHandle<Self>) {
+		// Drawing would map the surface again after `hide`
+		if !self.app.running() {
+			return;
+		}
+		let Some(surface) = self.surface().cloned() else {
+			return;
+		};
+		self.follow_output();
+		let (width, height) = self.physical_size();
+		let stride = width as i32 * 4;
+		let [r, g, b] = self.app.colors().fg;
+		let border_color = [b, g, r, 0xff];
+
+		let size = (stride * height as i32) as usize;
+		if self.pool.len() < size {
+			self.pool.resize(size).expect("resize memory pool");
+		}
 		let (buffer, canvas) = self
 			.pool
 			.create_buffer(
@@ -352,40 +909,36 @@ impl Window {
 			)
 			.expect("create buffer");
 
-		(0..self.width).for_each(|i| {
+		(0..width).for_each(|i| {
 			let index = 4 * i as usize;
-			canvas[index] = border_color;
-			canvas[index + 1] = border_color;
-			canvas[index + 2] = border_color;
-			canvas[index + 3] = 0xff;
+			canvas[index..index + 4].copy_from_slice(&border_color);
 		});
 
-		self.app
-			.draw(&mut canvas[4 * self.width as usize..], width, height);
+		// Everything between the borders belongs to the app
+		if height > 2 {
+			let row = 4 * width as usize;
+			let len = canvas.len();
+			self.app
+				.draw(&mut canvas[row..len - row], width, height - 2);
+		}
 
-		(0..self.width).for_each(|i| {
-			let index = canvas.len() - 4 * i as usize - 1;
-			canvas[index - 3] = border_color;
-			canvas[index - 2] = border_color;
-			canvas[index - 1] = border_color;
-			canvas[index] = 0xff;
+		(0..width).for_each(|i| {
+			let index = canvas.len() - 4 * (i as usize + 1);
+			canvas[index..index + 4].copy_from_slice(&border_color);
 		});
 
 		// Damage the entire window
-		self.layer
-			.wl_surface()
-			.damage_buffer(0, 0, width as i32, height as i32);
+		surface.damage_buffer(0, 0, width as i32, height as i32);
 
 		// Request our next frame
-		self.layer
-			.wl_surface()
-			.frame(qh, self.layer.wl_surface().clone());
+		surface.frame(qh, surface.clone());
 
 		// Attach and commit to present.
-		buffer
-			.attach_to(self.layer.wl_surface())
-			.expect("buffer attach");
-		self.layer.commit();
+		self.set_buffer_scale(&surface);
+		buffer.attach_to(&surface).expect("buffer attach");
+		surface.commit();
+
+		self.update_text_input();
 
 		// TODO save and reuse buffer when the window size is unchanged.  This is especially
 		// useful if you do damage tracking, since you don't need to redraw the undamaged parts
@@ -403,6 +956,11 @@ delegate_pointer!(Window);
 
 delegate_layer!(Window);
 
+delegate_data_device!(Window);
+delegate_primary_selection!(Window);
+
+delegate_activation!(Window);
+
 delegate_registry!(Window);
 
 impl ProvidesRegistryState for Window {
@@ -411,3 +969,34 @@ impl ProvidesRegistryState for Window {
 	}
 	registry_handlers![OutputState, SeatState];
 }
+
+/// Length of `bytes` without a UTF-8 character cut in half at the end, as
+/// that would decode to U+FFFD
+fn complete_utf8(bytes: &[u8]) -> usize {
+	// Where the last character starts, continuation bytes are `0b10xxxxxx`
+	let start = bytes
+		.iter()
+		.rposition(|byte| byte & 0xc0 != 0x80)
+		.filter(|start| bytes.len() - start < 4)
+		.unwrap_or(bytes.len());
+	match std::str::from_utf8(&bytes[start..]) {
+		Err(err) if err.error_len().is_none() => start,
+		_ => bytes.len(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn complete_utf8_drops_a_cut_character() {
+		assert_eq!(complete_utf8(b"abc"), 3);
+		assert_eq!(complete_utf8("a€".as_bytes()), 4);
+		assert_eq!(complete_utf8(&"a€".as_bytes()[..3]), 1);
+		assert_eq!(complete_utf8(&"a😀".as_bytes()[..4]), 1);
+		// Only the end is cut short, invalid bytes elsewhere are kept
+		assert_eq!(complete_utf8(b"\xffa"), 2);
+		assert_eq!(complete_utf8(b""), 0);
+	}
+}
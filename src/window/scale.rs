@@ -0,0 +1,137 @@
+use super::Window;
+use smithay_client_toolkit::globals::GlobalData;
+use wayland_client::{protocol::wl_surface::WlSurface, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols::wp::{
+	fractional_scale::v1::client::{
+		wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+		wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+	},
+	viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+};
+
+/// Fractional scaling through `wp_fractional_scale_v1`, with the buffer
+/// mapped back to the logical size through `wp_viewporter`
+pub struct FractionalScale {
+	fractional_scale: WpFractionalScaleV1,
+	viewport: WpViewport,
+	/// The scale preferred by the compositor, in 120ths
+	preferred: u32,
+}
+
+impl FractionalScale {
+	/// Returns `None` if the compositor doesn't support both protocols
+	pub fn new(
+		manager: Option<&WpFractionalScaleManagerV1>,
+		viewporter: Option<&WpViewporter>,
+		surface: &WlSurface,
+		qh: &QueueHandle<Window>,
+	) -> Option<Self> {
+		// Checked up front, so neither object is created without the other
+		let (manager, viewporter) = manager.zip(viewporter)?;
+		Some(Self {
+			fractional_scale: manager.get_fractional_scale(surface, qh, ()),
+			viewport: viewporter.get_viewport(surface, qh, ()),
+			preferred: 120,
+		})
+	}
+}
+
+impl Drop for FractionalScale {
+	fn drop(&mut self) {
+		self.fractional_scale.destroy();
+		self.viewport.destroy();
+	}
+}
+
+impl Window {
+	/// Ratio of physical pixels to logical pixels
+	pub(super) fn scale(&self) -> f64 {
+		match &self.fractional_scale {
+			Some(fractional_scale) => fractional_scale.preferred as f64 / 120.,
+			None => self.scale_factor as f64,
+		}
+	}
+
+	/// Size of the buffer in physical pixels
+	pub(super) fn physical_size(&self) -> (u32, u32) {
+		let scale = self.scale();
+		(
+			(self.width as f64 * scale).round() as u32,
+			(self.height as f64 * scale).round() as u32,
+		)
+	}
+
+	/// Tells the compositor how the buffer maps to the surface, called before
+	/// attaching a buffer
+	pub(super) fn set_buffer_scale(&self, surface: &WlSurface) {
+		match &self.fractional_scale {
+			Some(fractional_scale) => fractional_scale
+				.viewport
+				.set_destination(self.width as i32, self.height as i32),
+			None => surface.set_buffer_scale(self.scale_factor),
+		}
+	}
+
+	/// Re-rasterizes the glyphs for the new scale
+	pub(super) fn scale_changed(&mut self) {
+		let scale = self.scale();
+		self.app.set_scale(scale);
+	}
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, GlobalData> for Window {
+	fn event(
+		_: &mut Self,
+		_: &WpFractionalScaleManagerV1,
+		_: <WpFractionalScaleManagerV1 as Proxy>::Event,
+		_: &GlobalData,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+	) {
+		// No events
+	}
+}
+
+impl Dispatch<WpViewporter, GlobalData> for Window {
+	fn event(
+		_: &mut Self,
+		_: &WpViewporter,
+		_: <WpViewporter as Proxy>::Event,
+		_: &GlobalData,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+	) {
+		// No events
+	}
+}
+
+impl Dispatch<WpViewport, ()> for Window {
+	fn event(
+		_: &mut Self,
+		_: &WpViewport,
+		_: <WpViewport as Proxy>::Event,
+		_: &(),
+		_: &Connection,
+		_: &QueueHandle<Self>,
+	) {
+		// No events
+	}
+}
+
+impl Dispatch<WpFractionalScaleV1, ()> for Window {
+	fn event(
+		state: &mut Self,
+		_: &WpFractionalScaleV1,
+		event: wp_fractional_scale_v1::Event,
+		_: &(),
+		_: &Connection,
+		_: &QueueHandle<Self>,
+	) {
+		if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+			if let Some(fractional_scale) = state.fractional_scale.as_mut() {
+				fractional_scale.preferred = scale;
+			}
+			state.scale_changed();
+		}
+	}
+}
@@ -0,0 +1,218 @@
+use super::Window;
+use crate::events::Event;
+use smithay_client_toolkit::globals::GlobalData;
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols::wp::text_input::zv3::client::{
+	zwp_text_input_manager_v3::ZwpTextInputManagerV3,
+	zwp_text_input_v3::{self, ContentHint, ContentPurpose, ZwpTextInputV3},
+};
+
+/// Most bytes of surrounding text the protocol allows
+const MAX_SURROUNDING_TEXT: usize = 4000;
+
+/// Input method support through `zwp_text_input_v3`
+pub struct TextInput {
+	text_input: ZwpTextInputV3,
+	enabled: bool,
+	/// Events are double buffered by the protocol, and only take effect on `done`
+	pending: Pending,
+	/// The state last sent to the compositor
+	sent: Option<State>,
+}
+
+#[derive(PartialEq)]
+struct State {
+	rectangle: (i32, i32, i32, i32),
+	text: String,
+	cursor: usize,
+}
+
+#[derive(Default)]
+struct Pending {
+	preedit: Option<(String, Option<usize>)>,
+	commit: Option<String>,
+	delete: (usize, usize),
+}
+
+impl TextInput {
+	pub fn new(text_input: ZwpTextInputV3) -> Self {
+		Self {
+			text_input,
+			enabled: false,
+			pending: Pending::default(),
+			sent: None,
+		}
+	}
+}
+
+impl Window {
+	/// Tells the input method where the cursor is and what text surrounds it
+	pub(super) fn update_text_input(&mut self) {
+		// The app works in physical pixels, the protocol in surface local ones
+		let scale = self.scale();
+		let Some(text_input) = self.text_input.as_mut().filter(|i| i.enabled) else {
+			return;
+		};
+		let (x, y, width, height) = self.app.cursor_rectangle();
+		let logical = |i: i32| (i as f64 / scale).round() as i32;
+		// +1 for the top border
+		let (x, y, width, height) = (
+			logical(x),
+			logical(y + 1),
+			logical(width).max(1),
+			logical(height),
+		);
+		let (text, cursor) = self.app.surrounding_text();
+		let (text, cursor) = surrounding_window(text, cursor);
+		let state = State {
+			rectangle: (x, y, width, height),
+			text: text.to_string(),
+			cursor,
+		};
+		if text_input.sent.as_ref() == Some(&state) {
+			return;
+		}
+		text_input.text_input.set_surrounding_text(
+			state.text.clone(),
+			cursor as i32,
+			cursor as i32,
+		);
+		text_input
+			.text_input
+			.set_cursor_rectangle(x, y, width, height);
+		text_input.text_input.commit();
+		text_input.sent = Some(state);
+	}
+}
+
+/// The part of `text` around the byte offset `cursor` that fits into a
+/// request, along with the cursor's offset into it
+fn surrounding_window(text: &str, cursor: usize) -> (&str, usize) {
+	let mut start = cursor
+		.saturating_sub(MAX_SURROUNDING_TEXT / 2)
+		.min(text.len().saturating_sub(MAX_SURROUNDING_TEXT));
+	while !text.is_char_boundary(start) {
+		start += 1;
+	}
+	let mut end = (start + MAX_SURROUNDING_TEXT).min(text.len());
+	while !text.is_char_boundary(end) {
+		end -= 1;
+	}
+	(&text[start..end], cursor - start)
+}
+
+impl Dispatch<ZwpTextInputManagerV3, GlobalData> for Window {
+	fn event(
+		_: &mut Self,
+		_: &ZwpTextInputManagerV3,
+		_: <ZwpTextInputManagerV3 as Proxy>::Event,
+		_: &GlobalData,
+		_: &Connection,
+		_: &QueueHandle<Self>,
+	) {
+		// No events
+	}
+}
+
+impl Dispatch<ZwpTextInputV3, ()> for Window {
+	fn event(
+		state: &mut Self,
+		_: &ZwpTextInputV3,
+		event: zwp_text_input_v3::Event,
+		_: &(),
+		conn: &Connection,
+		qh: &QueueHandle<Self>,
+	) {
+		// Only our own surface is of interest
+		if let zwp_text_input_v3::Event::Enter { surface } = &event {
+			if state.surface() != Some(surface) {
+				return;
+			}
+		}
+		let Some(text_input) = state.text_input.as_mut() else {
+			return;
+		};
+		match event {
+			zwp_text_input_v3::Event::Enter { .. } => {
+				text_input.enabled = true;
+				text_input.sent = None;
+				text_input.text_input.enable();
+				text_input
+					.text_input
+					.set_content_type(ContentHint::None, ContentPurpose::Normal);
+				state.update_text_input();
+			}
+			zwp_text_input_v3::Event::Leave { .. } => {
+				text_input.enabled = false;
+				text_input.text_input.disable();
+				text_input.text_input.commit();
+				state.handle_event(
+					conn,
+					qh,
+					Event::Preedit {
+						text: String::new(),
+						cursor: None,
+					},
+				);
+			}
+			zwp_text_input_v3::Event::PreeditString {
+				text, cursor_begin, ..
+			} => {
+				let cursor = usize::try_from(cursor_begin).ok();
+				text_input.pending.preedit = text.map(|text| (text, cursor));
+			}
+			zwp_text_input_v3::Event::CommitString { text } => text_input.pending.commit = text,
+			zwp_text_input_v3::Event::DeleteSurroundingText {
+				before_length,
+				after_length,
+			} => text_input.pending.delete = (before_length as usize, after_length as usize),
+			zwp_text_input_v3::Event::Done { .. } => {
+				let Pending {
+					preedit,
+					commit,
+					delete: (before, after),
+				} = std::mem::take(&mut text_input.pending);
+				// Applied in the order the protocol specifies
+				if before != 0 || after != 0 {
+					state.handle_event(conn, qh, Event::DeleteSurrounding { before, after });
+				}
+				if let Some(text) = commit {
+					state.handle_event(conn, qh, Event::Commit(text));
+				}
+				let (text, cursor) = preedit.unwrap_or_default();
+				state.handle_event(conn, qh, Event::Preedit { text, cursor });
+			}
+			_ => (),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn short_text_is_sent_whole() {
+		assert_eq!(surrounding_window("query", 2), ("query", 2));
+	}
+
+	#[test]
+	fn long_text_is_cut_around_the_cursor() {
+		let text = "a".repeat(10_000);
+		let (window, cursor) = surrounding_window(&text, 5000);
+		assert_eq!(window.len(), MAX_SURROUNDING_TEXT);
+		assert_eq!(cursor, MAX_SURROUNDING_TEXT / 2);
+		let (window, cursor) = surrounding_window(&text, 9990);
+		assert_eq!(window.len(), MAX_SURROUNDING_TEXT);
+		assert_eq!(cursor, MAX_SURROUNDING_TEXT - 10);
+		assert_eq!(surrounding_window(&text, 10).1, 10);
+	}
+
+	#[test]
+	fn long_text_is_cut_between_characters() {
+		let text = "€".repeat(3000);
+		let (window, cursor) = surrounding_window(&text, 4500);
+		assert!(window.len() <= MAX_SURROUNDING_TEXT);
+		assert_eq!(&window[..cursor], &text[4500 - cursor..4500]);
+	}
+}